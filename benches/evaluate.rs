@@ -0,0 +1,62 @@
+use coolrule::Value;
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use std::collections::HashMap;
+
+// Rules are compiled when they're created, so this times evaluating the
+// compiled rule. The `bench_compile` test compares it with walking the parsed
+// expression.
+fn evaluate(c: &mut Criterion) {
+    let item = |price: f64, quantity: f64| {
+        Value::Map(HashMap::from([
+            ("price".to_string(), Value::Number(price)),
+            ("quantity".to_string(), Value::Number(quantity)),
+        ]))
+    };
+    let context = HashMap::from([
+        (vec!["user", "age"], Value::Number(36.0)),
+        (vec!["user", "country"], Value::Str("NL".to_string())),
+        (vec!["user", "plan"], Value::Str("pro".to_string())),
+        (
+            vec!["order", "tags"],
+            Value::List(vec![
+                Value::Str("gift".to_string()),
+                Value::Str("sale".to_string()),
+            ]),
+        ),
+        (vec!["order", "total"], Value::Number(120.0)),
+        (
+            vec!["order", "items"],
+            Value::List(vec![item(5.0, 2.0), item(20.0, 1.0), item(12.5, 4.0)]),
+        ),
+    ]);
+    let rules = [
+        // The expression from bench.py
+        ("literals", "true == false or (1, 2, 3) ⊆ (1, 2, 3)"),
+        (
+            "comparisons",
+            "user.age >= 18 and user.plan != \"free\" and order.total > 100",
+        ),
+        (
+            "membership",
+            "user.country in (\"GB\", \"FR\", \"DE\", \"ES\", \"IT\", \"NL\", \"BE\", \"SE\") \
+             and user.plan notin (\"free\", \"trial\")",
+        ),
+        (
+            "sets",
+            "order.tags ⊆ (\"gift\", \"sale\", \"new\", \"bulk\") and order.tags ∩ (\"sale\")",
+        ),
+        (
+            "quantifier",
+            "any(item in order.items: item.price * item.quantity > 40)",
+        ),
+    ];
+    for (name, expr) in rules {
+        let rule = coolrule::new(expr).unwrap();
+        c.bench_function(name, |b| {
+            b.iter(|| rule.test_with_context(black_box(&context)).unwrap())
+        });
+    }
+}
+
+criterion_group!(benches, evaluate);
+criterion_main!(benches);
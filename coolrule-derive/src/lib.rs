@@ -0,0 +1,134 @@
+//! `#[derive(RuleContext)]` for [coolrule](https://crates.io/crates/coolrule).
+//!
+//! Use it through coolrule's `derive` feature rather than depending on this
+//! crate directly. See `coolrule::RuleContext` for details.
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::{parse_macro_input, spanned::Spanned, Attribute, Data, DeriveInput, Fields, LitStr};
+
+#[proc_macro_derive(RuleContext, attributes(rule))]
+pub fn derive_rule_context(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    let expanded = match &input.data {
+        Data::Struct(data) => derive_struct(&input, &data.fields),
+        Data::Enum(data) => derive_enum(&input, data.variants.iter().collect()),
+        Data::Union(_) => Err(syn::Error::new(
+            input.ident.span(),
+            "RuleContext can't be derived for unions",
+        )),
+    };
+    expanded
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+// What `#[rule(...)]` says about a field or variant
+#[derive(Default)]
+struct Options {
+    rename: Option<String>,
+    skip: bool,
+}
+
+fn options(attrs: &[Attribute]) -> syn::Result<Options> {
+    let mut options = Options::default();
+    for attr in attrs.iter().filter(|attr| attr.path().is_ident("rule")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("rename") {
+                options.rename = Some(meta.value()?.parse::<LitStr>()?.value());
+                Ok(())
+            } else if meta.path.is_ident("skip") {
+                options.skip = true;
+                Ok(())
+            } else {
+                Err(meta.error("expected `rename = \"...\"` or `skip`"))
+            }
+        })?;
+    }
+    Ok(options)
+}
+
+// Each field is reachable by its (possibly renamed) name. `get` only converts
+// the fields along the path rather than the whole struct.
+fn derive_struct(input: &DeriveInput, fields: &Fields) -> syn::Result<TokenStream2> {
+    let Fields::Named(fields) = fields else {
+        return Err(syn::Error::new(
+            fields.span(),
+            "RuleContext can only be derived for structs with named fields",
+        ));
+    };
+    let mut names = vec![];
+    let mut idents = vec![];
+    for field in &fields.named {
+        let options = options(&field.attrs)?;
+        if options.skip {
+            continue;
+        }
+        let ident = field.ident.clone().unwrap();
+        names.push(options.rename.unwrap_or_else(|| ident.to_string()));
+        idents.push(ident);
+    }
+
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::coolrule::RuleContext for #name #ty_generics #where_clause {
+            fn to_value(&self) -> ::coolrule::Value {
+                ::coolrule::Value::Map(::std::collections::HashMap::from([
+                    #((
+                        #names.to_string(),
+                        ::coolrule::RuleContext::to_value(&self.#idents),
+                    ),)*
+                ]))
+            }
+
+            fn get(&self, path: &[&str]) -> ::std::option::Option<::coolrule::Value> {
+                match path.split_first() {
+                    ::std::option::Option::None => {
+                        ::std::option::Option::Some(::coolrule::RuleContext::to_value(self))
+                    }
+                    #(::std::option::Option::Some((&#names, rest)) => {
+                        ::coolrule::RuleContext::get(&self.#idents, rest)
+                    })*
+                    _ => ::std::option::Option::None,
+                }
+            }
+        }
+    })
+}
+
+// Unit variants become strings of their (possibly renamed) names
+fn derive_enum(input: &DeriveInput, variants: Vec<&syn::Variant>) -> syn::Result<TokenStream2> {
+    let mut names = vec![];
+    let mut idents = vec![];
+    for variant in variants {
+        if !matches!(variant.fields, Fields::Unit) {
+            return Err(syn::Error::new(
+                variant.span(),
+                "RuleContext can only be derived for enums whose variants have no fields",
+            ));
+        }
+        let options = options(&variant.attrs)?;
+        if options.skip {
+            return Err(syn::Error::new(
+                variant.span(),
+                "enum variants can't be skipped",
+            ));
+        }
+        names.push(options.rename.unwrap_or_else(|| variant.ident.to_string()));
+        idents.push(&variant.ident);
+    }
+
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::coolrule::RuleContext for #name #ty_generics #where_clause {
+            fn to_value(&self) -> ::coolrule::Value {
+                match self {
+                    #(#name::#idents => ::coolrule::Value::Str(#names.to_string()),)*
+                }
+            }
+        }
+    })
+}
@@ -0,0 +1,125 @@
+use coolrule::{CoolRuleError, EvalErrorKind, RuleContext, Value};
+
+#[derive(RuleContext)]
+enum Country {
+    #[rule(rename = "DE")]
+    Germany,
+    #[rule(rename = "FR")]
+    France,
+    Elsewhere,
+}
+
+#[derive(RuleContext)]
+struct Address {
+    country: Country,
+    city: Box<str>,
+}
+
+#[derive(RuleContext)]
+struct LineItem {
+    sku: &'static str,
+    price: f64,
+}
+
+#[derive(RuleContext)]
+struct Order {
+    #[rule(rename = "total")]
+    total_cents: u64,
+    items: Vec<LineItem>,
+    shipping: Option<Address>,
+    billing: Option<Address>,
+    #[rule(skip)]
+    #[allow(dead_code)]
+    internal_notes: String,
+    tags: Vec<String>,
+}
+
+#[derive(RuleContext)]
+struct Wrapper<'a> {
+    order: &'a Order,
+}
+
+fn order() -> Order {
+    Order {
+        total_cents: 12_500,
+        items: vec![
+            LineItem {
+                sku: "a1",
+                price: 25.0,
+            },
+            LineItem {
+                sku: "b2",
+                price: 100.0,
+            },
+        ],
+        shipping: Some(Address {
+            country: Country::Germany,
+            city: "Berlin".into(),
+        }),
+        billing: None,
+        internal_notes: "fragile".to_string(),
+        tags: vec!["gift".to_string()],
+    }
+}
+
+#[test]
+fn test_derive() {
+    let order = order();
+    let exprs = [
+        ("total > 10000", true),
+        ("items[1].price = 100 and \"a1\" in items[*].sku", true),
+        ("any(item in items: item.price > 50)", true),
+        (
+            "shipping.country = \"DE\" and shipping.city = \"Berlin\"",
+            true,
+        ),
+        ("shipping.country in (\"FR\", \"Elsewhere\")", false),
+        ("billing is none", true),
+        ("tags ∩ (\"gift\", \"sale\")", true),
+        ("len(items) = 2", true),
+    ];
+    for (expr, expected) in exprs.iter() {
+        let cr = coolrule::new(expr).unwrap();
+        assert_eq!(cr.test_with(&order).unwrap(), *expected, "{expr}");
+    }
+
+    let wrapper = Wrapper { order: &order };
+    let cr = coolrule::new("order.items[0].sku = \"a1\"").unwrap();
+    assert!(cr.test_with(&wrapper).unwrap());
+
+    let missing = [
+        vec!["total_cents"],
+        vec!["internal_notes"],
+        vec!["billing", "city"],
+        vec!["shipping", "zip"],
+    ];
+    for path in missing.iter() {
+        let cr = coolrule::new(&format!("{} = 1", path.join("."))).unwrap();
+        match cr.test_with(&order) {
+            Err(CoolRuleError::EvalError(e)) => assert_eq!(
+                e.kind(),
+                &EvalErrorKind::MissingVariable {
+                    path: path.iter().map(|s| s.to_string()).collect()
+                }
+            ),
+            _ => panic!("{path:?} shouldn't be found"),
+        }
+    }
+}
+
+#[test]
+fn test_to_value() {
+    assert_eq!(Country::France.to_value(), Value::Str("FR".to_string()));
+    assert_eq!(
+        Country::Elsewhere.to_value(),
+        Value::Str("Elsewhere".to_string())
+    );
+    let Value::Map(order) = order().to_value() else {
+        panic!("structs should be maps");
+    };
+    let mut keys: Vec<_> = order.keys().map(|k| k.as_str()).collect();
+    keys.sort();
+    assert_eq!(keys, ["billing", "items", "shipping", "tags", "total"]);
+    assert_eq!(order["total"], Value::Number(12_500.0));
+    assert_eq!(order["billing"], Value::None);
+}
@@ -0,0 +1,323 @@
+use crate::{
+    evaluator::{operator, Compare},
+    functions::Function,
+    parser::{
+        Accessor, AndOr, ArithOp, BinOp, BooleanCondition, BooleanExpression, MatchOp, PropertyVal,
+        Quantifier, SimpleValue, Span, Spanned, ValueExpression,
+    },
+};
+use regex::Regex;
+use std::{borrow::Cow, collections::HashSet};
+
+// Rules are compiled once when they're created so that evaluating them
+// doesn't repeat the work of walking the AST: property paths are interned to
+// slots that are looked up at most once per evaluation, groups of literals
+// are evaluated ahead of time and hashed for the set operators, operators
+// are resolved to the functions that apply them, and values are borrowed
+// from the rule and the context rather than cloned. Every way of evaluating
+// a rule evaluates its program.
+
+// A value expression with its property paths resolved
+pub enum Expr {
+    Literal(SimpleValue),
+    // A path in the context, by its index in `Program::paths`
+    Slot(usize),
+    // A path under the item bound by the quantifier `depth` levels out from
+    // the innermost one, e.g. `x.y` in `any(x in xs: x.y = 1)`
+    Local { depth: usize, path: Vec<String> },
+    Access(Box<Expr>, Vec<String>, Vec<Accessor>),
+    Negate(Box<Expr>),
+    // A call along with where it is, which asynchronous evaluation remembers
+    // its results by
+    Call(Function, Vec<Expr>, Span),
+    Arithmetic(Box<Expr>, ArithOp, Box<Expr>),
+}
+
+// A side of a comparison
+pub enum Side {
+    Value(Expr),
+    Group(Vec<Expr>),
+    // A group that's all literals, so it's the same every evaluation
+    Literals(Vec<SimpleValue>),
+}
+
+// The operators that look values up in a group, which a group of literals
+// can answer from a set hashed when compiling
+#[derive(Clone, Copy)]
+pub enum SetOp {
+    In,
+    NotIn,
+    SubSetOf,
+    IntersectionOf,
+    NotIntersectionOf,
+}
+
+impl SetOp {
+    fn of(bin_op: BinOp) -> Option<SetOp> {
+        match bin_op {
+            BinOp::In => Some(SetOp::In),
+            BinOp::NotIn => Some(SetOp::NotIn),
+            BinOp::SubSetOf => Some(SetOp::SubSetOf),
+            BinOp::IntersectionOf => Some(SetOp::IntersectionOf),
+            BinOp::NotIntersectionOf => Some(SetOp::NotIntersectionOf),
+            _ => None,
+        }
+    }
+
+    pub fn bin_op(self) -> BinOp {
+        match self {
+            SetOp::In => BinOp::In,
+            SetOp::NotIn => BinOp::NotIn,
+            SetOp::SubSetOf => BinOp::SubSetOf,
+            SetOp::IntersectionOf => BinOp::IntersectionOf,
+            SetOp::NotIntersectionOf => BinOp::NotIntersectionOf,
+        }
+    }
+}
+
+// `in` finds values by equality, where `-0` equals `0` even though they hash
+// differently, so zeros are looked up as `0`. The other set operators have
+// always hashed values as they are.
+pub fn member(set_op: SetOp, value: &SimpleValue) -> Cow<'_, SimpleValue> {
+    match (set_op, value) {
+        (SetOp::In | SetOp::NotIn, SimpleValue::Number(n)) if *n == 0.0 => {
+            Cow::Owned(SimpleValue::Number(0.0))
+        }
+        _ => Cow::Borrowed(value),
+    }
+}
+
+pub enum Operator {
+    // The function that applies the operator
+    Apply(BinOp, Compare),
+    // A set operator with a group of literals on the right, along with the
+    // group's set
+    Set(SetOp, HashSet<SimpleValue>),
+}
+
+pub enum Condition {
+    Compare(Side, Operator, Side),
+    Match(Side, MatchOp, Regex),
+    Quantified(Quantifier, Side, Box<Node>),
+}
+
+// Groups are only there for parsing, so they're compiled away and the
+// program otherwise mirrors the expression
+pub enum Node {
+    Condition(Condition, Span),
+    Not(Box<Node>),
+    Binary(Box<Node>, AndOr, Box<Node>),
+}
+
+pub struct Program {
+    // The path of each slot
+    pub paths: Vec<Vec<String>>,
+    pub root: Node,
+}
+
+struct Compiler<'a> {
+    paths: Vec<Vec<String>>,
+    // The names bound by the enclosing quantifiers, innermost last
+    bound: Vec<&'a str>,
+}
+
+impl<'a> Compiler<'a> {
+    fn path(&mut self, path: &[String]) -> Expr {
+        if let Some(depth) = self.bound.iter().rev().position(|name| *name == path[0]) {
+            return Expr::Local {
+                depth,
+                path: path.to_vec(),
+            };
+        }
+        match self.paths.iter().position(|p| p == path) {
+            Some(slot) => Expr::Slot(slot),
+            None => {
+                self.paths.push(path.to_vec());
+                Expr::Slot(self.paths.len() - 1)
+            }
+        }
+    }
+
+    fn value_expression(&mut self, value_expression: &Spanned<ValueExpression>) -> Expr {
+        match &value_expression.node {
+            ValueExpression::Value(SimpleValue::PropertyPath(path)) => self.path(path),
+            ValueExpression::Value(v) => Expr::Literal(v.clone()),
+            ValueExpression::Access(path, accessors) => {
+                Expr::Access(Box::new(self.path(path)), path.clone(), accessors.clone())
+            }
+            ValueExpression::Negate(operand) => match self.value_expression(operand) {
+                // So that negative numbers are literals
+                Expr::Literal(SimpleValue::Number(n)) => Expr::Literal(SimpleValue::Number(-n)),
+                operand => Expr::Negate(Box::new(operand)),
+            },
+            ValueExpression::Call(function, args) => Expr::Call(
+                function.clone(),
+                args.iter().map(|arg| self.value_expression(arg)).collect(),
+                value_expression.span,
+            ),
+            ValueExpression::Arithmetic(lhs, arith_op, rhs) => Expr::Arithmetic(
+                Box::new(self.value_expression(lhs)),
+                *arith_op,
+                Box::new(self.value_expression(rhs)),
+            ),
+        }
+    }
+
+    fn side(&mut self, property_val: &PropertyVal) -> Side {
+        match property_val {
+            PropertyVal::Value(v) => Side::Value(self.value_expression(v)),
+            PropertyVal::Group(members) => {
+                let members: Vec<_> = members.iter().map(|v| self.value_expression(v)).collect();
+                let literals = members
+                    .iter()
+                    .map(|member| match member {
+                        Expr::Literal(v) => Some(v.clone()),
+                        _ => None,
+                    })
+                    .collect();
+                match literals {
+                    Some(literals) => Side::Literals(literals),
+                    None => Side::Group(members),
+                }
+            }
+        }
+    }
+
+    fn boolean_condition(&mut self, boolean_condition: &'a BooleanCondition, span: Span) -> Node {
+        let condition = match boolean_condition {
+            BooleanCondition::Comparison(lval, bin_op, rval) => {
+                let (lhs, rhs) = (self.side(&lval.node), self.side(&rval.node));
+                let operator = match (SetOp::of(bin_op.node), &rhs) {
+                    (Some(set_op), Side::Literals(items)) => Operator::Set(
+                        set_op,
+                        items
+                            .iter()
+                            .map(|item| member(set_op, item).into_owned())
+                            .collect(),
+                    ),
+                    _ => Operator::Apply(bin_op.node, operator(bin_op.node)),
+                };
+                Condition::Compare(lhs, operator, rhs)
+            }
+            BooleanCondition::Match(lval, match_op, pattern) => {
+                Condition::Match(self.side(&lval.node), match_op.node, pattern.node.clone())
+            }
+            BooleanCondition::Quantified(quantifier, variable, collection, body) => {
+                let collection = self.side(&collection.node);
+                self.bound.push(&variable.node);
+                let body = self.boolean_expression(body);
+                self.bound.pop();
+                Condition::Quantified(*quantifier, collection, Box::new(body))
+            }
+            BooleanCondition::Group(expr) => return self.boolean_expression(expr),
+            BooleanCondition::Not(cond) => {
+                return Node::Not(Box::new(self.boolean_condition(&cond.node, cond.span)))
+            }
+        };
+        Node::Condition(condition, span)
+    }
+
+    fn boolean_expression(&mut self, boolean_expression: &'a BooleanExpression) -> Node {
+        match boolean_expression {
+            BooleanExpression::Condition(cond) => self.boolean_condition(&cond.node, cond.span),
+            BooleanExpression::Binary(lhs, and_or, rhs) => Node::Binary(
+                Box::new(self.boolean_expression(lhs)),
+                *and_or,
+                Box::new(self.boolean_expression(rhs)),
+            ),
+        }
+    }
+}
+
+pub fn compile(boolean_expression: &BooleanExpression) -> Program {
+    let mut compiler = Compiler {
+        paths: vec![],
+        bound: vec![],
+    };
+    let root = compiler.boolean_expression(boolean_expression);
+    Program {
+        paths: compiler.paths,
+        root,
+    }
+}
+
+#[test]
+fn test_compile() {
+    use crate::{parser::parse, walker::eval_both, Comparison, Evaluation, Options, Value};
+    use std::collections::HashMap;
+
+    let context = HashMap::from([
+        (vec!["a"], Value::Number(1.0)),
+        (vec!["zero"], Value::Number(-0.0)),
+        (vec!["s"], Value::Str("Hello".to_string())),
+        (
+            vec!["xs"],
+            Value::List(vec![Value::Number(1.0), Value::Number(2.0)]),
+        ),
+        (
+            vec!["users"],
+            Value::List(vec![
+                Value::Map(HashMap::from([
+                    ("name".to_string(), Value::Str("ada".to_string())),
+                    (
+                        "roles".to_string(),
+                        Value::List(vec![Value::Str("admin".to_string())]),
+                    ),
+                ])),
+                Value::Map(HashMap::from([
+                    ("name".to_string(), Value::Str("bob".to_string())),
+                    ("roles".to_string(), Value::List(vec![])),
+                ])),
+            ]),
+        ),
+    ]);
+    // Compiled rules evaluate the same as walking the expression
+    let exprs = [
+        "a = 1 and s != \"x\" or a > xs",
+        "a in (1, 2) and a notin (3, a + 3) and s in (\"Hello\")",
+        "zero in (0, 1) and -0 in (zero) and a in xs and not xs in (1, 2)",
+        "xs ⊆ (1, 2, 3) and xs ⊇ (1) and xs ∩ (2, 5) and xs not∩ (7)",
+        "a ⊆ (1, 2)",
+        "(1, 2) ⊆ xs and (a, 2) = xs and (1, 2) in (1, 2)",
+        "xs > (0, 1) and -a < 0 and a - -1 = 2 and 7 % 4 = 3",
+        "lower(s) starts_with \"he\" and s like \"H%\" and s =~ \"l+o$\"",
+        "(s, a) =~ \"x\"",
+        "any(u in users: \"admin\" in u.roles) and all(u in users: len(u.name) = 3)",
+        "none(u in users: any(a in u.roles: a = \"root\")) and a = 1",
+        "any(x in xs: any(y in xs: x + y = 4 and a = 1))",
+        "any(x in a: x = 1)",
+        "users[0].name = \"ada\" and users[*].name ∩ (\"bob\") and len(users[-1].roles) = 0",
+        "users[2].name = \"ada\"",
+        "a / 0 = 1 or a = 1",
+        "not (a = 2 or not s = \"Hello\")",
+        "1 = \"1\" or s > 2",
+        "a = 2 and a / 0 = 1",
+    ];
+    let eager = Options {
+        evaluation: Evaluation::Eager,
+        ..Default::default()
+    };
+    let strict = Options {
+        comparison: Comparison::Strict,
+        ..Default::default()
+    };
+    for options in [Options::default(), eager, strict] {
+        for expr in exprs {
+            let boolean_expression = parse(expr, &options).unwrap();
+            let _ = eval_both(&boolean_expression, &context, &options);
+        }
+    }
+
+    // Each context path gets one slot, while paths under a quantifier's
+    // variable don't get any
+    let boolean_expression = parse(
+        "a = 1 and (a in (1, 2) or any(a in xs: a.b = c)) and c.d = a",
+        &Options::default(),
+    )
+    .unwrap();
+    let program = compile(&boolean_expression);
+    assert_eq!(
+        program.paths,
+        [vec!["a"], vec!["xs"], vec!["c"], vec!["c", "d"]]
+    );
+}
@@ -0,0 +1,185 @@
+use crate::{ContextProvider, Value};
+use std::collections::{BTreeMap, HashMap};
+
+/// A Rust value that rules can be tested against with
+/// [`CoolRule::test_with`](crate::CoolRule::test_with).
+///
+/// With the `derive` feature, `#[derive(RuleContext)]` implements this for
+/// structs with named fields, where each field is reachable by its name, and
+/// for enums with unit variants, which become strings. Fields can be renamed
+/// with `#[rule(rename = "...")]` and left out with `#[rule(skip)]`.
+///
+/// ```
+/// # #[cfg(feature = "derive")] {
+/// use coolrule::RuleContext;
+///
+/// #[derive(RuleContext)]
+/// enum Plan {
+///     Free,
+///     #[rule(rename = "pro")]
+///     Professional,
+/// }
+///
+/// #[derive(RuleContext)]
+/// struct Account {
+///     plan: Plan,
+///     seats: u32,
+/// }
+///
+/// #[derive(RuleContext)]
+/// struct User {
+///     #[rule(rename = "email")]
+///     email_address: String,
+///     account: Option<Account>,
+///     roles: Vec<String>,
+/// }
+///
+/// let user = User {
+///     email_address: "ada@corp.com".to_string(),
+///     account: Some(Account { plan: Plan::Professional, seats: 3 }),
+///     roles: vec!["admin".to_string()],
+/// };
+/// let expr = coolrule::new("account.plan = \"pro\" and \"admin\" in roles").unwrap();
+/// assert!(expr.test_with(&user).unwrap());
+/// # }
+/// ```
+pub trait RuleContext {
+    /// Converts the whole value, e.g. to a [`Value::Map`] of a struct's fields.
+    fn to_value(&self) -> Value;
+
+    /// The value at `path` below this one, or `None` if there isn't one. An
+    /// empty path is the value itself.
+    fn get(&self, path: &[&str]) -> Option<Value> {
+        let mut value = self.to_value();
+        for segment in path {
+            match value {
+                Value::Map(mut map) => value = map.remove(*segment)?,
+                _ => return None,
+            }
+        }
+        Some(value)
+    }
+}
+
+// Provides the values of paths in a `RuleContext`
+pub(crate) struct Derived<'a, T: ?Sized>(pub &'a T);
+
+impl<T: RuleContext + ?Sized> ContextProvider for Derived<'_, T> {
+    fn get(&self, path: &[&str]) -> Option<Value> {
+        self.0.get(path)
+    }
+}
+
+impl RuleContext for Value {
+    fn to_value(&self) -> Value {
+        self.clone()
+    }
+}
+
+impl RuleContext for bool {
+    fn to_value(&self) -> Value {
+        Value::Bool(*self)
+    }
+}
+
+impl RuleContext for String {
+    fn to_value(&self) -> Value {
+        Value::Str(self.clone())
+    }
+}
+
+impl RuleContext for str {
+    fn to_value(&self) -> Value {
+        Value::Str(self.to_string())
+    }
+}
+
+macro_rules! number_rule_context {
+    ($($t:ty),*) => {
+        $(impl RuleContext for $t {
+            fn to_value(&self) -> Value {
+                Value::Number(*self as f64)
+            }
+        })*
+    };
+}
+
+number_rule_context!(f32, f64, i8, i16, i32, i64, isize, u8, u16, u32, u64, usize);
+
+impl<T: RuleContext + ?Sized> RuleContext for &T {
+    fn to_value(&self) -> Value {
+        (**self).to_value()
+    }
+
+    fn get(&self, path: &[&str]) -> Option<Value> {
+        (**self).get(path)
+    }
+}
+
+impl<T: RuleContext + ?Sized> RuleContext for Box<T> {
+    fn to_value(&self) -> Value {
+        (**self).to_value()
+    }
+
+    fn get(&self, path: &[&str]) -> Option<Value> {
+        (**self).get(path)
+    }
+}
+
+// `None` is `none` in rules
+impl<T: RuleContext> RuleContext for Option<T> {
+    fn to_value(&self) -> Value {
+        match self {
+            Some(value) => value.to_value(),
+            None => Value::None,
+        }
+    }
+
+    fn get(&self, path: &[&str]) -> Option<Value> {
+        match self {
+            Some(value) => value.get(path),
+            None if path.is_empty() => Some(Value::None),
+            None => None,
+        }
+    }
+}
+
+impl<T: RuleContext> RuleContext for Vec<T> {
+    fn to_value(&self) -> Value {
+        Value::List(self.iter().map(RuleContext::to_value).collect())
+    }
+}
+
+impl<T: RuleContext> RuleContext for HashMap<String, T> {
+    fn to_value(&self) -> Value {
+        Value::Map(
+            self.iter()
+                .map(|(k, v)| (k.clone(), v.to_value()))
+                .collect(),
+        )
+    }
+
+    fn get(&self, path: &[&str]) -> Option<Value> {
+        match path.split_first() {
+            None => Some(self.to_value()),
+            Some((key, rest)) => self.get(*key)?.get(rest),
+        }
+    }
+}
+
+impl<T: RuleContext> RuleContext for BTreeMap<String, T> {
+    fn to_value(&self) -> Value {
+        Value::Map(
+            self.iter()
+                .map(|(k, v)| (k.clone(), v.to_value()))
+                .collect(),
+        )
+    }
+
+    fn get(&self, path: &[&str]) -> Option<Value> {
+        match path.split_first() {
+            None => Some(self.to_value()),
+            Some((key, rest)) => self.get(*key)?.get(rest),
+        }
+    }
+}
@@ -0,0 +1,156 @@
+use crate::parser::Span;
+use std::{error::Error, fmt};
+
+/// Describes why an expression couldn't be parsed and where.
+///
+/// The `Display` implementation renders the offending source line with a
+/// caret under the problem, e.g.
+///
+/// ```text
+/// expected a value after `>=`
+///   |
+/// 1 | x >=
+///   |     ^ expected a number, a string, `true`, `false`, `none`, a property path or `(`
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    /// A human readable description of the problem.
+    pub message: String,
+    /// The bytes of the expression the problem points at. Empty at the end of input.
+    pub span: Span,
+    /// What would have been accepted at `span`.
+    pub expected: Vec<String>,
+    /// The 1-based line of `span.start`.
+    pub line: usize,
+    /// The 1-based column, in characters, of `span.start`.
+    pub column: usize,
+    source_line: String,
+    // The number of characters to underline
+    width: usize,
+}
+
+impl Diagnostic {
+    pub(crate) fn new(
+        source: &str,
+        position: usize,
+        message: String,
+        expected: Vec<String>,
+    ) -> Diagnostic {
+        // Point at the next token rather than any whitespace before it
+        let start = source[position..]
+            .find(|c: char| !c.is_whitespace())
+            .map_or(source.len(), |offset| position + offset);
+        let end = start + token_len(source.as_bytes(), start);
+
+        let line_start = source[..start].rfind('\n').map_or(0, |i| i + 1);
+        let line_end = source[start..]
+            .find('\n')
+            .map_or(source.len(), |i| start + i);
+        Diagnostic {
+            message,
+            span: Span { start, end },
+            expected,
+            line: source[..start].matches('\n').count() + 1,
+            column: source[line_start..start].chars().count() + 1,
+            source_line: source[line_start..line_end]
+                .trim_end_matches('\r')
+                .to_string(),
+            width: source[start..end].chars().count().max(1),
+        }
+    }
+}
+
+// The length in bytes of the word or character at `position`
+fn token_len(input: &[u8], position: usize) -> usize {
+    let is_word = |b: &u8| b.is_ascii_alphanumeric() || *b == b'_';
+    match input.get(position) {
+        None => 0,
+        Some(b) if is_word(b) => input[position..].iter().take_while(|b| is_word(b)).count(),
+        Some(_) => String::from_utf8_lossy(&input[position..])
+            .chars()
+            .next()
+            .map_or(1, |c| c.len_utf8()),
+    }
+}
+
+// Describes the token at `position` for messages like "unexpected `foo`"
+pub(crate) fn describe_token(input: &[u8], position: usize) -> String {
+    match token_len(input, position) {
+        0 => "end of input".to_string(),
+        len => format!(
+            "`{}`",
+            String::from_utf8_lossy(&input[position..position + len])
+        ),
+    }
+}
+
+impl Error for Diagnostic {}
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let line = self.line.to_string();
+        let gutter = " ".repeat(line.len());
+        writeln!(f, "{}", self.message)?;
+        writeln!(f, "{gutter} |")?;
+        writeln!(f, "{line} | {}", self.source_line)?;
+        write!(
+            f,
+            "{gutter} | {}{}",
+            " ".repeat(self.column - 1),
+            "^".repeat(self.width)
+        )?;
+        match self.expected.as_slice() {
+            [] => Ok(()),
+            [only] => write!(f, " expected {only}"),
+            [init @ .., last] => write!(f, " expected {} or {last}", init.join(", ")),
+        }
+    }
+}
+
+#[test]
+fn test_diagnostic() {
+    use crate::{parser::parse, Options};
+
+    let exprs = [
+        (
+            "x >=",
+            (1, 5),
+            "expected a value after `>=`
+  |
+1 | x >=
+  |     ^ expected a number, a string, `true`, `false`, `none`, a property path or `(`",
+        ),
+        (
+            "x = 1 andy = 2",
+            (1, 7),
+            "unexpected `andy`
+  |
+1 | x = 1 andy = 2
+  |       ^^^^ expected `and`, `or` or end of input",
+        ),
+        (
+            "x ≠ 1 and\r\n  y ≥ ≥",
+            (2, 7),
+            "expected a value after `≥`
+  |
+2 |   y ≥ ≥
+  |       ^ expected a number, a string, `true`, `false`, `none`, a property path or `(`",
+        ),
+        (
+            "a = 1 or\nb = 1 or\nc = 1 or\nd = 1 or\ne = 1 or\nf = 1 or\ng = 1 or\nh = 1 or\ni = 1 or\nj = \"k",
+            (10, 5),
+            "unterminated string
+   |
+10 | j = \"k
+   |     ^ expected `\"`",
+        ),
+    ];
+    for (expr, (line, column), rendered) in exprs.iter() {
+        let diagnostic = parse(expr, &Options::default()).unwrap_err();
+        assert_eq!(
+            (diagnostic.line, diagnostic.column),
+            (*line, *column),
+            "{expr}"
+        );
+        assert_eq!(diagnostic.to_string(), *rendered, "{expr}");
+    }
+}
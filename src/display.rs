@@ -0,0 +1,184 @@
+use crate::parser::{
+    Accessor, AndOr, ArithOp, BooleanCondition, BooleanExpression, PropertyVal, Quantifier,
+    SimpleValue, ValueExpression,
+};
+use std::fmt;
+
+// Expressions are written back out in a form that parses to the same AST,
+// adding parentheses wherever the grouping would otherwise be lost
+
+impl fmt::Display for BooleanExpression {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BooleanExpression::Condition(cond) => write!(f, "{}", cond.node),
+            BooleanExpression::Binary(lhs, and_or, rhs) => {
+                // Mixed chains are parenthesised so they mean the same thing
+                // with either `Precedence`
+                let operand = |expr: &BooleanExpression| match expr {
+                    BooleanExpression::Binary(_, op, _) if op != and_or => format!("({expr})"),
+                    _ => expr.to_string(),
+                };
+                let and_or = match and_or {
+                    AndOr::And => "and",
+                    AndOr::Or => "or",
+                };
+                write!(f, "{} {and_or} {}", operand(lhs), operand(rhs))
+            }
+        }
+    }
+}
+
+impl fmt::Display for BooleanCondition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BooleanCondition::Comparison(lval, bin_op, rval) => {
+                write!(f, "{} {} {}", lval.node, bin_op.node, rval.node)
+            }
+            BooleanCondition::Match(lval, match_op, pattern) => {
+                write!(
+                    f,
+                    "{} {} \"{}\"",
+                    lval.node,
+                    match_op.node,
+                    pattern.node.as_str()
+                )
+            }
+            BooleanCondition::Quantified(quantifier, variable, collection, body) => {
+                let quantifier = match quantifier {
+                    Quantifier::Any => "any",
+                    Quantifier::All => "all",
+                    Quantifier::None => "none",
+                };
+                write!(
+                    f,
+                    "{quantifier}({} in {}: {body})",
+                    variable.node, collection.node
+                )
+            }
+            BooleanCondition::Group(expr) => write!(f, "({expr})"),
+            BooleanCondition::Not(cond) => write!(f, "not {}", cond.node),
+        }
+    }
+}
+
+impl fmt::Display for PropertyVal {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PropertyVal::Value(v) => write!(f, "{}", v.node),
+            PropertyVal::Group(members) => {
+                let members: Vec<_> = members.iter().map(|v| v.node.to_string()).collect();
+                write!(f, "({})", members.join(", "))
+            }
+        }
+    }
+}
+
+// How tightly an arithmetic operator binds
+fn binding(arith_op: ArithOp) -> u8 {
+    match arith_op {
+        ArithOp::Add | ArithOp::Sub => 1,
+        ArithOp::Mul | ArithOp::Div | ArithOp::Rem => 2,
+    }
+}
+
+impl fmt::Display for ValueExpression {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ValueExpression::Value(SimpleValue::Str(s)) => write!(f, "\"{s}\""),
+            ValueExpression::Value(SimpleValue::PropertyPath(path)) => {
+                write!(f, "{}", path.join("."))
+            }
+            ValueExpression::Value(v) => write!(f, "{v}"),
+            ValueExpression::Access(path, accessors) => {
+                write!(f, "{}", path.join("."))?;
+                for accessor in accessors {
+                    match accessor {
+                        Accessor::Index(index) => write!(f, "[{index}]")?,
+                        Accessor::Wildcard => write!(f, "[*]")?,
+                        Accessor::Field(name) => write!(f, ".{name}")?,
+                    }
+                }
+                Ok(())
+            }
+            ValueExpression::Negate(operand) => match &operand.node {
+                ValueExpression::Value(SimpleValue::Number(_))
+                | ValueExpression::Negate(_)
+                | ValueExpression::Arithmetic(..) => write!(f, "-({})", operand.node),
+                _ => write!(f, "-{}", operand.node),
+            },
+            ValueExpression::Call(function, args) => {
+                let args: Vec<_> = args.iter().map(|arg| arg.node.to_string()).collect();
+                write!(f, "{}({})", function.name(), args.join(", "))
+            }
+            ValueExpression::Arithmetic(lhs, arith_op, rhs) => {
+                // Chains are left-associative, so only a looser operator on the
+                // left needs parentheses but any operator on the right does
+                let lhs = match &lhs.node {
+                    ValueExpression::Arithmetic(_, op, _) if binding(*op) < binding(*arith_op) => {
+                        format!("({})", lhs.node)
+                    }
+                    _ => lhs.node.to_string(),
+                };
+                let rhs = match &rhs.node {
+                    ValueExpression::Arithmetic(_, op, _) if binding(*op) <= binding(*arith_op) => {
+                        format!("({})", rhs.node)
+                    }
+                    _ => rhs.node.to_string(),
+                };
+                write!(f, "{lhs} {arith_op} {rhs}")
+            }
+        }
+    }
+}
+
+#[test]
+fn test_display() {
+    use crate::{parser::parse, Options, Precedence};
+
+    let exprs = [
+        ("x = 1", "x = 1"),
+        ("x==1 AND y ≠ \"a b\"", "x = 1 and y != \"a b\""),
+        ("a = 1 or b = 2 and c = 3", "a = 1 or (b = 2 and c = 3)"),
+        ("(a = 1 or b = 2) and c = 3", "(a = 1 or b = 2) and c = 3"),
+        ("a = 1 and b = 2 and c = 3", "a = 1 and b = 2 and c = 3"),
+        (
+            "!(x in (1, 2.5, -3)) and not y ∩ (\"a\")",
+            "not (x in (1, 2.5, -3)) and not y ∩ (\"a\")",
+        ),
+        ("a - (b - c) * 2 = -(d + 1)", "a - (b - c) * 2 = -(d + 1)"),
+        ("(a - b) - c * (d % e) > 1", "a - b - c * (d % e) > 1"),
+        ("- -x = 1", "-(-x) = 1"),
+        (
+            "lower(user.email) ends_with \"@corp.com\"",
+            "lower(user.email) ends_with \"@corp.com\"",
+        ),
+        (
+            "items[0].price > 1 and \"a\" in items[*].sku",
+            "items[0].price > 1 and \"a\" in items[*].sku",
+        ),
+        (
+            "any(x in xs: x.y is none or x.z isnot true)",
+            "any(x in xs: x.y is none or x.z isnot true)",
+        ),
+        ("name matches \"^a.*\\d$\"", "name =~ \"^a.*\\d$\""),
+        (
+            "name like \"a%\" and name not∩ (1)",
+            "name like \"a%\" and name not∩ (1)",
+        ),
+    ];
+    for (expr, expected) in exprs.iter() {
+        let boolean_expression = parse(expr, &Options::default()).unwrap();
+        let displayed = boolean_expression.to_string();
+        assert_eq!(displayed, *expected, "{expr}");
+        // Parsing the output gives the same expression again
+        let reparsed = parse(&displayed, &Options::default()).unwrap();
+        assert_eq!(reparsed.to_string(), displayed, "{expr}");
+    }
+
+    let options = Options {
+        precedence: Precedence::Boolrule,
+        ..Default::default()
+    };
+    let boolean_expression = parse("a = 1 and b = 2 or c = 3", &options).unwrap();
+    assert_eq!(boolean_expression.to_string(), "a = 1 and (b = 2 or c = 3)");
+}
@@ -1,333 +1,1240 @@
-use crate::parser::{AndOr, BinOp, BooleanCondition, BooleanExpression, PropertyVal, SimpleValue};
+use crate::{
+    compiler::{member, Condition, Expr, Node, Operator, Program, SetOp, Side},
+    explain::{Outcome, Step, Trace},
+    functions::{self, Function},
+    parser::{
+        Accessor, AndOr, ArithOp, BinOp, BooleanCondition, BooleanExpression, MatchOp, Quantifier,
+        SimpleValue, Span, Spanned,
+    },
+    AsyncContextProvider, ContextProvider, Options, Value,
+};
+use regex::Regex;
 use std::{
+    borrow::Cow,
+    cell::{OnceCell, RefCell},
     cmp::Ordering,
     collections::{HashMap, HashSet},
     error::Error,
     fmt,
+    future::{poll_fn, Future},
     hash::Hash,
     hash::Hasher,
+    task::Poll,
 };
 
-fn get_context_value(
-    key: Vec<&str>,
-    context: &HashMap<Vec<&str>, SimpleValue>,
-) -> Result<SimpleValue, EvalError> {
-    match context.get(&key) {
-        Some(v) => match v {
-            SimpleValue::PropertyPath(_) => Err(EvalError {
-                message: format!("property paths shouldn't be in the context dictionary"),
-            }),
-            _ => Ok(v.to_owned()),
-        },
-        None => {
-            let formatted = key.join(".");
-            Err(EvalError {
-                message: format!("{formatted} missing from context"),
+// The results of conditions by where they are in the expression
+type Decided = HashMap<Span, Result<Truth, EvalError>>;
+
+// The results of host function calls by where the call is and its arguments
+type Called = Vec<(Span, Vec<Value>, Result<SimpleValue, EvalErrorKind>)>;
+
+// What asynchronous evaluation has learnt so far, kept between rounds of
+// fetching: the values of the paths fetched so far, including `None` for the
+// missing ones, the conditions that were decided and the host functions that
+// were called
+struct Fetched {
+    slots: Vec<OnceCell<Option<SimpleValue>>>,
+    decided: Decided,
+    called: Called,
+}
+
+/// Controls how the operands of `and`/`or` are evaluated.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Evaluation {
+    /// Operands are evaluated left to right and the right-hand side is
+    /// skipped once the result is known, so `x isnot none and x.y > 3` is
+    /// `false` rather than an error when `x` is none. Errors on the left are
+    /// always surfaced, errors on the right only when it is reached.
+    #[default]
+    ShortCircuit,
+    /// Every operand is evaluated left to right and the first error is
+    /// surfaced even if it couldn't change the result. Useful for strictly
+    /// validating that a rule can be evaluated against a context.
+    Eager,
+}
+
+/// The outcome of evaluating with
+/// [`CoolRule::test_with_unknowns`](crate::CoolRule::test_with_unknowns),
+/// where a condition that reads a missing property path is `Unknown` rather
+/// than an error.
+///
+/// `and`, `or` and `not` follow SQL's three-valued logic: `false and unknown`
+/// is `false`, `true or unknown` is `true`, and otherwise an unknown operand
+/// makes the result unknown. Quantifiers likewise treat their items as an
+/// `or` (`any`) or an `and` (`all`, `none`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Truth {
+    True,
+    False,
+    Unknown,
+}
+
+impl Truth {
+    pub fn and(self, other: Truth) -> Truth {
+        match (self, other) {
+            (Truth::False, _) | (_, Truth::False) => Truth::False,
+            (Truth::True, Truth::True) => Truth::True,
+            _ => Truth::Unknown,
+        }
+    }
+
+    pub fn or(self, other: Truth) -> Truth {
+        match (self, other) {
+            (Truth::True, _) | (_, Truth::True) => Truth::True,
+            (Truth::False, Truth::False) => Truth::False,
+            _ => Truth::Unknown,
+        }
+    }
+}
+
+impl std::ops::Not for Truth {
+    type Output = Truth;
+
+    fn not(self) -> Truth {
+        match self {
+            Truth::True => Truth::False,
+            Truth::False => Truth::True,
+            Truth::Unknown => Truth::Unknown,
+        }
+    }
+}
+
+impl From<bool> for Truth {
+    fn from(b: bool) -> Truth {
+        match b {
+            true => Truth::True,
+            false => Truth::False,
+        }
+    }
+}
+
+/// Controls how comparisons between values of different types behave.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Comparison {
+    /// Values of different types are never equal and never ordered, so
+    /// `7 = true` and `age > "thirty"` are `false`.
+    #[default]
+    Loose,
+    /// Ordering values of different types, or checking values of different
+    /// types for equality, fails the evaluation with
+    /// [`EvalErrorKind::TypeMismatch`]. Any value can still be compared with
+    /// `none` by `=`, `!=`, `is` and `isnot`.
+    Strict,
+}
+
+pub fn negate(value: &SimpleValue) -> Result<SimpleValue, EvalErrorKind> {
+    match value {
+        SimpleValue::Number(n) => Ok(SimpleValue::Number(-n)),
+        v => Err(EvalErrorKind::NonNumeric {
+            op: "-".to_string(),
+            value: v.to_string(),
+        }),
+    }
+}
+
+pub fn arithmetic(
+    lhs: &SimpleValue,
+    arith_op: ArithOp,
+    rhs: &SimpleValue,
+) -> Result<SimpleValue, EvalErrorKind> {
+    let (l, r) = match (lhs, rhs) {
+        (SimpleValue::Number(l), SimpleValue::Number(r)) => (*l, *r),
+        (SimpleValue::Number(_), v) | (v, _) => {
+            return Err(EvalErrorKind::NonNumeric {
+                op: arith_op.to_string(),
+                value: v.to_string(),
             })
         }
+    };
+    match arith_op {
+        ArithOp::Add => Ok(SimpleValue::Number(l + r)),
+        ArithOp::Sub => Ok(SimpleValue::Number(l - r)),
+        ArithOp::Mul => Ok(SimpleValue::Number(l * r)),
+        ArithOp::Div | ArithOp::Rem if r == 0.0 => Err(EvalErrorKind::DivisionByZero),
+        ArithOp::Div => Ok(SimpleValue::Number(l / r)),
+        ArithOp::Rem => Ok(SimpleValue::Number(l % r)),
     }
 }
 
-fn eval_boolean_condition(
-    boolean_condition: &BooleanCondition,
-    context: &HashMap<Vec<&str>, SimpleValue>,
-) -> Result<bool, EvalError> {
-    match boolean_condition {
-        BooleanCondition::Comparison(lval, bin_op, rval) => match (lval, rval) {
-            (PropertyVal::SimpleValue(_sv1), PropertyVal::SimpleValue(_sv2)) => {
-                let sv1: SimpleValue = match _sv1 {
-                    SimpleValue::PropertyPath(p) => {
-                        get_context_value(p.iter().map(|s| s.as_str()).collect(), context)?
-                    }
-                    _ => _sv1.clone(),
-                };
-                let sv2: SimpleValue = match _sv2 {
-                    SimpleValue::PropertyPath(p) => {
-                        get_context_value(p.iter().map(|s| s.as_str()).collect(), context)?
+// Applies each accessor in turn. After a `[*]` the rest apply to every item
+// and the result is a list of what they produced.
+pub fn eval_access(
+    path: &[String],
+    value: SimpleValue,
+    accessors: &[Accessor],
+) -> Result<SimpleValue, EvalErrorKind> {
+    let mut path = path.to_vec();
+    let mut values = vec![value];
+    let mut projected = false;
+    for accessor in accessors {
+        let mut next = vec![];
+        for value in values {
+            match (accessor, value) {
+                (Accessor::Index(index), SimpleValue::List(mut items)) => {
+                    let len = items.len();
+                    let position = if *index < 0 {
+                        len as i64 + index
+                    } else {
+                        *index
+                    };
+                    if !(0..len as i64).contains(&position) {
+                        return Err(EvalErrorKind::IndexOutOfRange {
+                            path,
+                            index: *index,
+                            len,
+                        });
                     }
-                    _ => _sv2.clone(),
-                };
-                match bin_op {
-                    BinOp::Equal => Ok(sv1 == sv2),
-                    BinOp::NotEqual => Ok(sv1 != sv2),
-                    BinOp::GreaterThan => Ok(sv1.partial_cmp(&sv2) == Some(Ordering::Greater)),
-                    BinOp::GreaterThanOrEqual => {
-                        Ok(sv1 == sv2 || sv1.partial_cmp(&sv2) == Some(Ordering::Greater))
-                    }
-                    BinOp::LessThan => Ok(sv1.partial_cmp(&sv2) == Some(Ordering::Less)),
-                    BinOp::LessThanOrEqual => {
-                        Ok(sv1 == sv2 || sv1.partial_cmp(&sv2) == Some(Ordering::Less))
-                    }
-                    BinOp::In => Err(EvalError {
-                        message: format!("{sv2} is not iterable"),
-                    }),
-                    BinOp::NotIn => Err(EvalError {
-                        message: format!("{sv2} is not iterable"),
-                    }),
-                    BinOp::Is => Ok(sv1 == sv2),
-                    BinOp::IsNot => Ok(sv1 != sv2),
-                    BinOp::SubSetOf => Err(EvalError {
-                        message: format!("{sv2} is not iterable"),
-                    }),
-                    BinOp::SuperSetOf => Err(EvalError {
-                        message: format!("{sv2} is not iterable"),
-                    }),
-                    BinOp::IntersectionOf => Err(EvalError {
-                        message: format!("{sv2} is not iterable"),
-                    }),
-                    BinOp::NotIntersectionOf => Err(EvalError {
-                        message: format!("{sv2} is not iterable"),
-                    }),
+                    next.push(items.swap_remove(position as usize));
                 }
-            }
-            (PropertyVal::SimpleValue(_sv), PropertyVal::Group(_gv)) => {
-                let sv: SimpleValue = match _sv {
-                    SimpleValue::PropertyPath(p) => {
-                        get_context_value(p.iter().map(|s| s.as_str()).collect(), context)?
-                    }
-                    _ => _sv.clone(),
-                };
-                let mut gv: Vec<SimpleValue> = vec![];
-                for v in _gv.iter() {
-                    gv.push(match v {
-                        SimpleValue::PropertyPath(p) => {
-                            get_context_value(p.iter().map(|s| s.as_str()).collect(), context)?
-                        }
-                        _ => v.clone(),
+                (Accessor::Wildcard, SimpleValue::List(items)) => next.extend(items),
+                (Accessor::Index(_) | Accessor::Wildcard, value) => {
+                    return Err(EvalErrorKind::NotIterable {
+                        value: value.to_string(),
                     })
                 }
-                match bin_op {
-                    BinOp::Equal => Ok(false),
-                    BinOp::NotEqual => Ok(true),
-                    BinOp::GreaterThan => Err(EvalError {
-                        message: format!("{sv} is not iterable"),
-                    }),
-                    BinOp::GreaterThanOrEqual => Err(EvalError {
-                        message: format!("{sv} is not iterable"),
-                    }),
-                    BinOp::LessThan => Err(EvalError {
-                        message: format!("{sv} is not iterable"),
-                    }),
-                    BinOp::LessThanOrEqual => Err(EvalError {
-                        message: format!("{sv} is not iterable"),
-                    }),
-                    BinOp::In => {
-                        for v in gv {
-                            if sv == v {
-                                return Ok(true);
-                            }
-                        }
-                        return Ok(false);
+                (Accessor::Field(name), SimpleValue::Map(mut map)) if map.contains_key(name) => {
+                    next.push(map.remove(name).unwrap())
+                }
+                (Accessor::Field(name), _) => {
+                    path.push(name.clone());
+                    return Err(EvalErrorKind::MissingVariable { path });
+                }
+            }
+        }
+        path.push(match accessor {
+            Accessor::Index(index) => index.to_string(),
+            Accessor::Wildcard => "*".to_string(),
+            Accessor::Field(name) => name.clone(),
+        });
+        projected |= matches!(accessor, Accessor::Wildcard);
+        values = next;
+    }
+    match projected {
+        true => Ok(SimpleValue::List(values)),
+        false => Ok(values.pop().unwrap()),
+    }
+}
+
+// An evaluated side of a comparison, which may borrow from a compiled rule
+pub enum Operand<'a> {
+    Value(Cow<'a, SimpleValue>),
+    Group(Cow<'a, [SimpleValue]>),
+}
+
+// The name of an operand's type, for errors
+fn type_name(operand: &Operand) -> &'static str {
+    match operand {
+        Operand::Value(v) => v.type_name(),
+        Operand::Group(_) => "group",
+    }
+}
+
+// Which operand types strict comparisons reject
+fn check_strict(bin_op: BinOp, lhs: &Operand, rhs: &Operand) -> Result<(), EvalErrorKind> {
+    let (left, right) = (type_name(lhs), type_name(rhs));
+    let mismatched = match bin_op {
+        BinOp::GreaterThan
+        | BinOp::GreaterThanOrEqual
+        | BinOp::LessThan
+        | BinOp::LessThanOrEqual => left != right,
+        BinOp::Equal | BinOp::NotEqual | BinOp::Is | BinOp::IsNot => {
+            left != right && left != "none" && right != "none"
+        }
+        _ => false,
+    };
+    match mismatched {
+        true => Err(EvalErrorKind::TypeMismatch {
+            op: bin_op.to_string(),
+            left: left.to_string(),
+            right: right.to_string(),
+        }),
+        false => Ok(()),
+    }
+}
+
+// The error for an operator that doesn't apply to the types of its operands
+fn mismatch(bin_op: BinOp, lhs: &Operand, rhs: &Operand) -> EvalErrorKind {
+    EvalErrorKind::TypeMismatch {
+        op: bin_op.to_string(),
+        left: type_name(lhs).to_string(),
+        right: type_name(rhs).to_string(),
+    }
+}
+
+// Applies a comparison operator to the evaluated sides of a comparison
+pub type Compare = fn(BinOp, Operand, Operand) -> Result<bool, EvalErrorKind>;
+
+// The function that applies `bin_op`, which compiled rules resolve once
+// rather than on every evaluation
+pub fn operator(bin_op: BinOp) -> Compare {
+    match bin_op {
+        BinOp::Equal | BinOp::Is => equal,
+        BinOp::NotEqual | BinOp::IsNot => |bin_op, lhs, rhs| Ok(!equal(bin_op, lhs, rhs)?),
+        BinOp::GreaterThan => |_, lhs, rhs| {
+            order(
+                lhs,
+                rhs,
+                |l, r| l.partial_cmp(r) == Some(Ordering::Greater),
+                |o| o == Some(Ordering::Greater),
+            )
+        },
+        BinOp::GreaterThanOrEqual => |_, lhs, rhs| {
+            order(
+                lhs,
+                rhs,
+                |l, r| l == r || l.partial_cmp(r) == Some(Ordering::Greater),
+                |o| matches!(o, Some(Ordering::Greater | Ordering::Equal)),
+            )
+        },
+        BinOp::LessThan => |_, lhs, rhs| {
+            order(
+                lhs,
+                rhs,
+                |l, r| l.partial_cmp(r) == Some(Ordering::Less),
+                |o| o == Some(Ordering::Less),
+            )
+        },
+        BinOp::LessThanOrEqual => |_, lhs, rhs| {
+            order(
+                lhs,
+                rhs,
+                |l, r| l == r || l.partial_cmp(r) == Some(Ordering::Less),
+                |o| matches!(o, Some(Ordering::Less | Ordering::Equal)),
+            )
+        },
+        BinOp::In => is_in,
+        BinOp::NotIn => |bin_op, lhs, rhs| Ok(!is_in(bin_op, lhs, rhs)?),
+        BinOp::SubSetOf => |_, lhs, rhs| groups(lhs, rhs, is_subset),
+        BinOp::SuperSetOf => |_, lhs, rhs| groups(lhs, rhs, is_super_set),
+        BinOp::IntersectionOf => |_, lhs, rhs| groups(lhs, rhs, intersection_of),
+        BinOp::NotIntersectionOf => |_, lhs, rhs| groups(lhs, rhs, not_intersection_of),
+        BinOp::StartsWith => |bin_op, lhs, rhs| predicate(bin_op, functions::starts_with, lhs, rhs),
+        BinOp::EndsWith => |bin_op, lhs, rhs| predicate(bin_op, functions::ends_with, lhs, rhs),
+        BinOp::Contains => |bin_op, lhs, rhs| match (lhs, rhs) {
+            (Operand::Group(items), Operand::Value(v)) => Ok(items.contains(&*v)),
+            (lhs, rhs) => predicate(bin_op, functions::contains, lhs, rhs),
+        },
+        BinOp::Like | BinOp::ILike | BinOp::Glob | BinOp::IGlob => like,
+    }
+}
+
+// Applies `bin_op` the way a compiled rule would
+pub fn compare(
+    lhs: Operand,
+    bin_op: BinOp,
+    rhs: Operand,
+    comparison: Comparison,
+) -> Result<bool, EvalErrorKind> {
+    if comparison == Comparison::Strict {
+        check_strict(bin_op, &lhs, &rhs)?;
+    }
+    operator(bin_op)(bin_op, lhs, rhs)
+}
+
+// A value is never equal to a group, and groups are equal item by item
+fn equal(_: BinOp, lhs: Operand, rhs: Operand) -> Result<bool, EvalErrorKind> {
+    Ok(match (lhs, rhs) {
+        (Operand::Value(l), Operand::Value(r)) => l == r,
+        (Operand::Group(l), Operand::Group(r)) => l == r,
+        _ => false,
+    })
+}
+
+// Values are ordered by `values`, and groups by whether `items` holds for
+// each pair of their items up to the end of the shorter one
+fn order(
+    lhs: Operand,
+    rhs: Operand,
+    values: fn(&SimpleValue, &SimpleValue) -> bool,
+    items: fn(Option<Ordering>) -> bool,
+) -> Result<bool, EvalErrorKind> {
+    match (lhs, rhs) {
+        (Operand::Value(l), Operand::Value(r)) => Ok(values(&l, &r)),
+        (Operand::Group(l), Operand::Group(r)) => {
+            Ok(l.iter().zip(r.iter()).all(|(l, r)| items(l.partial_cmp(r))))
+        }
+        (Operand::Value(v), _) | (_, Operand::Value(v)) => Err(EvalErrorKind::NotIterable {
+            value: v.to_string(),
+        }),
+    }
+}
+
+// A group is never in another group
+fn is_in(_: BinOp, lhs: Operand, rhs: Operand) -> Result<bool, EvalErrorKind> {
+    match (lhs, rhs) {
+        (Operand::Value(v), Operand::Group(items)) => Ok(items.contains(&*v)),
+        (Operand::Group(_), Operand::Group(_)) => Ok(false),
+        (_, Operand::Value(v)) => Err(EvalErrorKind::NotIterable {
+            value: v.to_string(),
+        }),
+    }
+}
+
+// The set operators need a group on both sides
+fn groups(
+    lhs: Operand,
+    rhs: Operand,
+    set_op: fn(&[SimpleValue], &[SimpleValue]) -> bool,
+) -> Result<bool, EvalErrorKind> {
+    match (lhs, rhs) {
+        (Operand::Group(l), Operand::Group(r)) => Ok(set_op(&l, &r)),
+        (_, Operand::Value(v)) | (Operand::Value(v), _) => Err(EvalErrorKind::NotIterable {
+            value: v.to_string(),
+        }),
+    }
+}
+
+// `x starts_with y` is the same as `starts_with(x, y)`
+fn predicate(
+    bin_op: BinOp,
+    function: fn(&[SimpleValue]) -> Result<SimpleValue, EvalErrorKind>,
+    lhs: Operand,
+    rhs: Operand,
+) -> Result<bool, EvalErrorKind> {
+    match (lhs, rhs) {
+        (Operand::Value(l), Operand::Value(r)) => {
+            Ok(function(&[l.into_owned(), r.into_owned()])? == SimpleValue::Bool(true))
+        }
+        (lhs, rhs) => Err(mismatch(bin_op, &lhs, &rhs)),
+    }
+}
+
+// `like`, `ilike`, `glob` and `iglob` match a string against a pattern
+fn like(bin_op: BinOp, lhs: Operand, rhs: Operand) -> Result<bool, EvalErrorKind> {
+    if let (Operand::Value(l), Operand::Value(r)) = (&lhs, &rhs) {
+        if let (SimpleValue::Str(s), SimpleValue::Str(pattern)) = (&**l, &**r) {
+            return Ok(wildcard_match(bin_op, s, pattern));
+        }
+    }
+    Err(mismatch(bin_op, &lhs, &rhs))
+}
+
+pub fn is_match(
+    value: &SimpleValue,
+    match_op: MatchOp,
+    pattern: &Regex,
+) -> Result<bool, EvalErrorKind> {
+    let SimpleValue::Str(s) = value else {
+        return Err(EvalErrorKind::TypeMismatch {
+            op: match_op.to_string(),
+            left: value.type_name().to_string(),
+            right: "pattern".to_string(),
+        });
+    };
+    match match_op {
+        MatchOp::Matches => Ok(pattern.is_match(s)),
+        MatchOp::NotMatches => Ok(!pattern.is_match(s)),
+    }
+}
+
+// What an item has to evaluate to for it to decide a quantifier, and what
+// the quantifier then evaluates to
+fn decisive(quantifier: Quantifier) -> (bool, bool) {
+    match quantifier {
+        Quantifier::Any => (true, true),
+        Quantifier::All => (false, false),
+        Quantifier::None => (true, false),
+    }
+}
+
+// The items bound by the enclosing quantifiers, innermost first
+struct Locals<'a> {
+    item: &'a SimpleValue,
+    parent: Option<&'a Locals<'a>>,
+}
+
+// The state of one evaluation of a program. With a context, each path is
+// looked up in it at most once. Without one, the paths that haven't been
+// fetched yet are collected so they can be fetched asynchronously before
+// evaluating again, and the conditions that were decided and the host
+// functions that were called are remembered so they aren't evaluated or
+// called again.
+struct Frame<'a> {
+    context: Option<&'a dyn ContextProvider>,
+    paths: &'a [Vec<String>],
+    slots: Vec<OnceCell<Option<SimpleValue>>>,
+    options: &'a Options,
+    // Whether a condition that reads a missing path is unknown rather than
+    // an error
+    unknowns: bool,
+    pending: RefCell<Vec<usize>>,
+    decided: RefCell<Decided>,
+    called: RefCell<Called>,
+}
+
+impl<'a> Frame<'a> {
+    fn new(
+        program: &'a Program,
+        context: &'a dyn ContextProvider,
+        options: &'a Options,
+        unknowns: bool,
+    ) -> Frame<'a> {
+        Frame {
+            context: Some(context),
+            paths: &program.paths,
+            slots: program.paths.iter().map(|_| OnceCell::new()).collect(),
+            options,
+            unknowns,
+            pending: RefCell::new(vec![]),
+            decided: RefCell::new(HashMap::new()),
+            called: RefCell::new(vec![]),
+        }
+    }
+
+    fn fetched(program: &'a Program, fetched: Fetched, options: &'a Options) -> Frame<'a> {
+        Frame {
+            context: None,
+            paths: &program.paths,
+            slots: fetched.slots,
+            options,
+            unknowns: false,
+            pending: RefCell::new(vec![]),
+            decided: RefCell::new(fetched.decided),
+            called: RefCell::new(fetched.called),
+        }
+    }
+}
+
+impl Frame<'_> {
+    fn slot(&self, slot: usize) -> Result<&SimpleValue, EvalErrorKind> {
+        let value = match self.context {
+            Some(context) => self.slots[slot].get_or_init(|| {
+                let path: Vec<&str> = self.paths[slot].iter().map(|s| s.as_str()).collect();
+                context.get(&path).map(|v| SimpleValue::from(&v))
+            }),
+            None => match self.slots[slot].get() {
+                Some(value) => value,
+                None => {
+                    // A stand-in so the rest of the condition can be
+                    // evaluated to find any other paths it needs
+                    let mut pending = self.pending.borrow_mut();
+                    if !pending.contains(&slot) {
+                        pending.push(slot);
                     }
-                    BinOp::NotIn => {
-                        for v in gv {
-                            if sv == v {
-                                return Ok(false);
-                            }
-                        }
-                        return Ok(true);
+                    return Ok(&SimpleValue::None);
+                }
+            },
+        };
+        match value {
+            Some(v) => Ok(v),
+            None => Err(EvalErrorKind::MissingVariable {
+                path: self.paths[slot].clone(),
+            }),
+        }
+    }
+
+    fn is_pending(&self) -> bool {
+        self.context.is_none() && !self.pending.borrow().is_empty()
+    }
+
+    // Results that depended on a stand-in value mean nothing, so evaluation
+    // stops before they can decide anything. The error is never surfaced.
+    fn waiting(&self) -> Result<(), EvalErrorKind> {
+        match self.pending.borrow().first() {
+            Some(slot) => Err(EvalErrorKind::MissingVariable {
+                path: self.paths[*slot].clone(),
+            }),
+            None => Ok(()),
+        }
+    }
+
+    fn check_pending(&self, span: Span) -> Result<(), EvalError> {
+        self.waiting().map_err(|kind| EvalError { kind, span })
+    }
+
+    // A condition is evaluated again once the paths it reads later on are
+    // fetched, so the host functions it calls before them are only called the
+    // first time
+    fn call(
+        &self,
+        function: &Function,
+        args: &[SimpleValue],
+        span: Span,
+    ) -> Result<SimpleValue, EvalErrorKind> {
+        if self.context.is_some() || !matches!(function, Function::Host(_)) {
+            return function.call(args);
+        }
+        let values: Vec<Value> = args.iter().cloned().map(Value::from).collect();
+        let called = self
+            .called
+            .borrow()
+            .iter()
+            .find(|(s, a, _)| *s == span && *a == values)
+            .map(|(_, _, result)| result.clone());
+        called.unwrap_or_else(|| {
+            let result = function.call(args);
+            self.called
+                .borrow_mut()
+                .push((span, values, result.clone()));
+            result
+        })
+    }
+
+    // The outcome of a condition that failed
+    fn failed(&self, kind: EvalErrorKind, span: Span) -> Result<Truth, EvalError> {
+        match kind {
+            EvalErrorKind::MissingVariable { .. } if self.unknowns => Ok(Truth::Unknown),
+            kind => Err(EvalError { kind, span }),
+        }
+    }
+
+    fn value<'a>(
+        &'a self,
+        expr: &'a Expr,
+        locals: Option<&'a Locals<'a>>,
+    ) -> Result<Cow<'a, SimpleValue>, EvalErrorKind> {
+        match expr {
+            Expr::Literal(v) => Ok(Cow::Borrowed(v)),
+            Expr::Slot(slot) => self.slot(*slot).map(Cow::Borrowed),
+            Expr::Local { depth, path } => {
+                let missing = || EvalErrorKind::MissingVariable { path: path.clone() };
+                let mut locals = locals;
+                for _ in 0..*depth {
+                    locals = locals.and_then(|l| l.parent);
+                }
+                let mut value = locals.ok_or_else(missing)?.item;
+                for segment in &path[1..] {
+                    match value {
+                        SimpleValue::Map(map) => value = map.get(segment).ok_or_else(missing)?,
+                        _ => return Err(missing()),
                     }
-                    BinOp::Is => Ok(false),
-                    BinOp::IsNot => Ok(true),
-                    BinOp::SubSetOf => Err(EvalError {
-                        message: format!("{sv} is not iterable"),
-                    }),
-                    BinOp::SuperSetOf => Err(EvalError {
-                        message: format!("{sv} is not iterable"),
-                    }),
-                    BinOp::IntersectionOf => Err(EvalError {
-                        message: format!("{sv} is not iterable"),
-                    }),
-                    BinOp::NotIntersectionOf => Err(EvalError {
-                        message: format!("{sv} is not iterable"),
-                    }),
                 }
+                Ok(Cow::Borrowed(value))
             }
-            (PropertyVal::Group(_), PropertyVal::SimpleValue(_sv)) => {
-                let sv: SimpleValue = match _sv {
-                    SimpleValue::PropertyPath(p) => {
-                        get_context_value(p.iter().map(|s| s.as_str()).collect(), context)?
-                    }
-                    _ => _sv.clone(),
-                };
-                match bin_op {
-                    BinOp::Equal => Ok(false),
-                    BinOp::NotEqual => Ok(true),
-                    BinOp::GreaterThan => Err(EvalError {
-                        message: format!("{sv} is not iterable"),
-                    }),
-                    BinOp::GreaterThanOrEqual => Err(EvalError {
-                        message: format!("{sv} is not iterable"),
-                    }),
-                    BinOp::LessThan => Err(EvalError {
-                        message: format!("{sv} is not iterable"),
-                    }),
-                    BinOp::LessThanOrEqual => Err(EvalError {
-                        message: format!("{sv} is not iterable"),
-                    }),
-                    BinOp::In => Err(EvalError {
-                        message: format!("{sv} is not iterable"),
-                    }),
-                    BinOp::NotIn => Err(EvalError {
-                        message: format!("{sv} is not iterable"),
-                    }),
-                    BinOp::Is => Ok(false),
-                    BinOp::IsNot => Ok(true),
-                    BinOp::SubSetOf => Err(EvalError {
-                        message: format!("{sv} is not iterable"),
-                    }),
-                    BinOp::SuperSetOf => Err(EvalError {
-                        message: format!("{sv} is not iterable"),
-                    }),
-                    BinOp::IntersectionOf => Err(EvalError {
-                        message: format!("{sv} is not iterable"),
-                    }),
-                    BinOp::NotIntersectionOf => Err(EvalError {
-                        message: format!("{sv} is not iterable"),
-                    }),
+            Expr::Access(base, path, accessors) => {
+                let value = self.value(base, locals)?.into_owned();
+                eval_access(path, value, accessors).map(Cow::Owned)
+            }
+            Expr::Negate(operand) => negate(&*self.value(operand, locals)?).map(Cow::Owned),
+            Expr::Call(function, args, span) => {
+                let args = args
+                    .iter()
+                    .map(|arg| self.value(arg, locals).map(Cow::into_owned))
+                    .collect::<Result<Vec<_>, _>>()?;
+                // Host functions aren't called with stand-in values
+                self.waiting()?;
+                self.call(function, &args, *span).map(Cow::Owned)
+            }
+            Expr::Arithmetic(lhs, arith_op, rhs) => {
+                let lhs = self.value(lhs, locals)?;
+                let rhs = self.value(rhs, locals)?;
+                arithmetic(&lhs, *arith_op, &rhs).map(Cow::Owned)
+            }
+        }
+    }
+
+    // A value that resolves to a list is treated the same as a group of values
+    fn operand<'a>(
+        &'a self,
+        side: &'a Side,
+        locals: Option<&'a Locals<'a>>,
+    ) -> Result<Operand<'a>, EvalErrorKind> {
+        match side {
+            Side::Value(expr) => Ok(match self.value(expr, locals)? {
+                Cow::Borrowed(SimpleValue::List(items)) => Operand::Group(Cow::Borrowed(items)),
+                Cow::Owned(SimpleValue::List(items)) => Operand::Group(Cow::Owned(items)),
+                v => Operand::Value(v),
+            }),
+            Side::Group(members) => members
+                .iter()
+                .map(|member| self.value(member, locals).map(Cow::into_owned))
+                .collect::<Result<Vec<_>, _>>()
+                .map(|items| Operand::Group(Cow::Owned(items))),
+            Side::Literals(items) => Ok(Operand::Group(Cow::Borrowed(items))),
+        }
+    }
+
+    // The value a pattern is matched against, which can't be a group
+    fn match_value<'a>(
+        &'a self,
+        lval: &'a Side,
+        match_op: MatchOp,
+        locals: Option<&'a Locals<'a>>,
+    ) -> Result<Cow<'a, SimpleValue>, EvalErrorKind> {
+        match lval {
+            Side::Value(expr) => self.value(expr, locals),
+            Side::Group(_) | Side::Literals(_) => Err(EvalErrorKind::TypeMismatch {
+                op: match_op.to_string(),
+                left: "group".to_string(),
+                right: "pattern".to_string(),
+            }),
+        }
+    }
+
+    // The items a quantifier goes through
+    fn items<'a>(
+        &'a self,
+        collection: &'a Side,
+        locals: Option<&'a Locals<'a>>,
+    ) -> Result<Cow<'a, [SimpleValue]>, EvalErrorKind> {
+        match self.operand(collection, locals)? {
+            Operand::Group(items) => Ok(items),
+            Operand::Value(value) => Err(EvalErrorKind::NotIterable {
+                value: value.to_string(),
+            }),
+        }
+    }
+
+    fn compare(
+        &self,
+        lhs: Operand,
+        operator: &Operator,
+        rhs: Operand,
+    ) -> Result<bool, EvalErrorKind> {
+        let comparison = self.options.comparison;
+        match operator {
+            Operator::Apply(bin_op, apply) => {
+                if comparison == Comparison::Strict {
+                    check_strict(*bin_op, &lhs, &rhs)?;
                 }
+                apply(*bin_op, lhs, rhs)
             }
-            (PropertyVal::Group(_gv1), PropertyVal::Group(_gv2)) => {
-                let mut gv1: Vec<SimpleValue> = vec![];
-                for v in _gv1.iter() {
-                    gv1.push(match v {
-                        SimpleValue::PropertyPath(p) => {
-                            get_context_value(p.iter().map(|s| s.as_str()).collect(), context)?
-                        }
-                        _ => v.clone(),
-                    })
+            Operator::Set(set_op, set) => match (set_op, lhs) {
+                (SetOp::In, Operand::Value(v)) => Ok(set.contains(&member(*set_op, &v))),
+                (SetOp::NotIn, Operand::Value(v)) => Ok(!set.contains(&member(*set_op, &v))),
+                (SetOp::SubSetOf, Operand::Group(g)) => Ok(g.iter().all(|item| set.contains(item))),
+                (SetOp::IntersectionOf, Operand::Group(g)) => {
+                    Ok(g.iter().any(|item| set.contains(item)))
                 }
-                let mut gv2: Vec<SimpleValue> = vec![];
-                for v in _gv2.iter() {
-                    gv2.push(match v {
-                        SimpleValue::PropertyPath(p) => {
-                            get_context_value(p.iter().map(|s| s.as_str()).collect(), context)?
-                        }
-                        _ => v.clone(),
-                    })
+                (SetOp::NotIntersectionOf, Operand::Group(g)) => {
+                    Ok(!g.iter().any(|item| set.contains(item)))
                 }
-                match bin_op {
-                    BinOp::Equal => {
-                        if gv1.len() != gv2.len() {
-                            return Ok(false);
-                        }
-                        for i in 0..gv1.len() {
-                            if gv1[i] != gv2[i] {
-                                return Ok(false);
-                            }
-                        }
-                        Ok(true)
-                    }
-                    BinOp::NotEqual => {
-                        if gv1.len() != gv2.len() {
-                            return Ok(true);
-                        }
-                        for i in 0..gv1.len() {
-                            if gv1[i] != gv2[i] {
-                                return Ok(true);
-                            }
-                        }
-                        Ok(false)
-                    }
-                    BinOp::GreaterThan => {
-                        for i in 0..usize::min(gv1.len(), gv2.len()) {
-                            if !(gv1[i] > gv2[i]) {
-                                return Ok(false);
-                            }
-                        }
-                        Ok(true)
-                    }
-                    BinOp::GreaterThanOrEqual => {
-                        for i in 0..usize::min(gv1.len(), gv2.len()) {
-                            if !(gv1[i] > gv2[i] || gv1[i] == gv2[i]) {
-                                return Ok(false);
-                            }
-                        }
-                        Ok(true)
-                    }
-                    BinOp::LessThan => {
-                        for i in 0..usize::min(gv1.len(), gv2.len()) {
-                            if !(gv1[i] < gv2[i]) {
-                                return Ok(false);
-                            }
-                        }
-                        Ok(true)
+                (set_op, lhs) => compare(lhs, set_op.bin_op(), rhs, comparison),
+            },
+        }
+    }
+
+    fn condition<'a>(
+        &'a self,
+        condition: &'a Condition,
+        span: Span,
+        locals: Option<&'a Locals<'a>>,
+    ) -> Result<Truth, EvalError> {
+        let result = match condition {
+            Condition::Compare(lhs, operator, rhs) => match self.operand(lhs, locals) {
+                // A stand-in for a path that hasn't been fetched may be why
+                // the left failed, so find the paths on the right too
+                Err(kind) if self.is_pending() => {
+                    let _ = self.operand(rhs, locals);
+                    Err(kind)
+                }
+                lhs => lhs.and_then(|lhs| {
+                    let rhs = self.operand(rhs, locals)?;
+                    self.compare(lhs, operator, rhs)
+                }),
+            },
+            Condition::Match(lval, match_op, pattern) => self
+                .match_value(lval, *match_op, locals)
+                .and_then(|value| is_match(&value, *match_op, pattern)),
+            Condition::Quantified(quantifier, collection, body) => {
+                let items = self.items(collection, locals);
+                self.check_pending(span)?;
+                match items {
+                    Ok(items) => return self.quantified(*quantifier, &items, body, locals),
+                    Err(kind) => Err(kind),
+                }
+            }
+        };
+        match result {
+            Ok(b) => Ok(Truth::from(b)),
+            Err(kind) => self.failed(kind, span),
+        }
+    }
+
+    // Evaluates `body` for each item. Like `and`/`or`, stops at the first item
+    // that decides the result unless evaluating eagerly.
+    fn quantified<'a>(
+        &'a self,
+        quantifier: Quantifier,
+        items: &'a [SimpleValue],
+        body: &'a Node,
+        locals: Option<&'a Locals<'a>>,
+    ) -> Result<Truth, EvalError> {
+        let (decisive, result) = decisive(quantifier);
+        let (decisive, result) = (Truth::from(decisive), Truth::from(result));
+        let mut outcome = !result;
+        for item in items {
+            let locals = Locals {
+                item,
+                parent: locals,
+            };
+            match self.node(body, Some(&locals))? {
+                truth if truth == decisive => {
+                    if self.options.evaluation == Evaluation::ShortCircuit {
+                        return Ok(result);
                     }
-                    BinOp::LessThanOrEqual => {
-                        for i in 0..usize::min(gv1.len(), gv2.len()) {
-                            if !(gv1[i] < gv2[i] || gv1[i] == gv2[i]) {
-                                return Ok(false);
-                            }
-                        }
-                        Ok(true)
+                    outcome = result;
+                }
+                // Unless another item decides the result, it isn't known
+                Truth::Unknown if outcome != result => outcome = Truth::Unknown,
+                _ => (),
+            }
+        }
+        Ok(outcome)
+    }
+
+    // Conditions outside of quantifiers that were decided in an earlier round
+    // of fetching aren't evaluated again
+    fn remembered<'a>(
+        &'a self,
+        condition: &'a Condition,
+        span: Span,
+        locals: Option<&'a Locals<'a>>,
+    ) -> Result<Truth, EvalError> {
+        if self.context.is_some() || locals.is_some() {
+            return self.condition(condition, span, locals);
+        }
+        if let Some(result) = self.decided.borrow().get(&span) {
+            return result.clone();
+        }
+        let result = self.condition(condition, span, locals);
+        if !self.is_pending() {
+            self.decided.borrow_mut().insert(span, result.clone());
+        }
+        result
+    }
+
+    // Whether the left of an `and`/`or` decides it without the right
+    fn short_circuits(&self, and_or: AndOr, lhs: Truth) -> bool {
+        self.options.evaluation == Evaluation::ShortCircuit
+            && matches!(
+                (and_or, lhs),
+                (AndOr::And, Truth::False) | (AndOr::Or, Truth::True)
+            )
+    }
+
+    fn node<'a>(
+        &'a self,
+        node: &'a Node,
+        locals: Option<&'a Locals<'a>>,
+    ) -> Result<Truth, EvalError> {
+        match node {
+            Node::Condition(condition, span) => {
+                let result = self.remembered(condition, *span, locals);
+                self.check_pending(*span)?;
+                result
+            }
+            Node::Not(node) => Ok(!self.node(node, locals)?),
+            Node::Binary(lhs, and_or, rhs) => {
+                let lhs = self.node(lhs, locals);
+                // When lhs is waiting on paths, the rhs is still reached if
+                // the evaluation is eager, so collect the paths it needs as well
+                if self.options.evaluation == Evaluation::Eager && self.is_pending() {
+                    let _ = self.node(rhs, locals);
+                }
+                let lhs = lhs?;
+                if self.short_circuits(*and_or, lhs) {
+                    return Ok(lhs);
+                }
+                let rhs = self.node(rhs, locals)?;
+                match and_or {
+                    AndOr::And => Ok(lhs.and(rhs)),
+                    AndOr::Or => Ok(lhs.or(rhs)),
+                }
+            }
+        }
+    }
+
+    // Like `node`, keeping the parts that were skipped. The program mirrors
+    // the expression apart from its groups, so the two are walked together
+    // for the source of each part.
+    fn trace_expression<'a>(
+        &'a self,
+        boolean_expression: &BooleanExpression,
+        node: &'a Node,
+        locals: Option<&'a Locals<'a>>,
+    ) -> Trace {
+        let (lhs, and_or, rhs, lhs_node, rhs_node) = match (boolean_expression, node) {
+            (BooleanExpression::Condition(cond), node) => {
+                return self.trace_condition(cond, node, locals)
+            }
+            (BooleanExpression::Binary(lhs, and_or, rhs), Node::Binary(lhs_node, _, rhs_node)) => {
+                (lhs, *and_or, rhs, lhs_node, rhs_node)
+            }
+            _ => unreachable!("a program mirrors its expression"),
+        };
+        let lhs = self.trace_expression(lhs, lhs_node, locals);
+        let decided = match &lhs.outcome {
+            Outcome::Failed(_) => true,
+            Outcome::Evaluated(b) => self.short_circuits(and_or, Truth::from(*b)),
+            Outcome::Skipped => false,
+        };
+        let (outcome, rhs) = match decided {
+            true => (lhs.outcome.clone(), skipped_expression(rhs)),
+            false => {
+                let rhs = self.trace_expression(rhs, rhs_node, locals);
+                let outcome = match (&lhs.outcome, &rhs.outcome) {
+                    (Outcome::Evaluated(l), Outcome::Evaluated(r)) => {
+                        Outcome::Evaluated(match and_or {
+                            AndOr::And => *l && *r,
+                            AndOr::Or => *l || *r,
+                        })
                     }
-                    BinOp::In => Ok(false),
-                    BinOp::NotIn => Ok(true),
-                    BinOp::Is => {
-                        if gv1.len() != gv2.len() {
-                            return Ok(false);
-                        }
-                        for i in 0..gv1.len() {
-                            if gv1[i] != gv2[i] {
-                                return Ok(false);
+                    (_, outcome) => outcome.clone(),
+                };
+                (outcome, rhs)
+            }
+        };
+        Trace {
+            source: boolean_expression.to_string(),
+            span: Span {
+                start: lhs.span.start,
+                end: rhs.span.end,
+            },
+            outcome,
+            step: binary_step(and_or, lhs, rhs),
+        }
+    }
+
+    // Like `condition`, recording the values each comparison saw
+    fn trace_condition<'a>(
+        &'a self,
+        boolean_condition: &Spanned<BooleanCondition>,
+        node: &'a Node,
+        locals: Option<&'a Locals<'a>>,
+    ) -> Trace {
+        let span = boolean_condition.span;
+        let (outcome, step) = match (&boolean_condition.node, node) {
+            (BooleanCondition::Group(expr), node) => {
+                let inner = self.trace_expression(expr, node, locals);
+                (inner.outcome.clone(), Step::Group(Box::new(inner)))
+            }
+            (BooleanCondition::Not(cond), Node::Not(node)) => {
+                let inner = self.trace_condition(cond, node, locals);
+                let outcome = match &inner.outcome {
+                    Outcome::Evaluated(b) => Outcome::Evaluated(!b),
+                    outcome => outcome.clone(),
+                };
+                (outcome, Step::Not(Box::new(inner)))
+            }
+            (
+                BooleanCondition::Comparison(_, bin_op, _),
+                Node::Condition(Condition::Compare(lhs, operator, rhs), _),
+            ) => {
+                let (result, left, right) = match self.operand(lhs, locals) {
+                    Err(kind) => (Err(kind), None, None),
+                    Ok(lhs) => {
+                        let left = Some(operand_value(&lhs));
+                        match self.operand(rhs, locals) {
+                            Err(kind) => (Err(kind), left, None),
+                            Ok(rhs) => {
+                                let right = Some(operand_value(&rhs));
+                                (self.compare(lhs, operator, rhs), left, right)
                             }
                         }
-                        Ok(true)
                     }
-                    BinOp::IsNot => {
-                        if gv1.len() != gv2.len() {
-                            return Ok(true);
-                        }
-                        for i in 0..gv1.len() {
-                            if gv1[i] != gv2[i] {
-                                return Ok(true);
-                            }
-                        }
-                        Ok(false)
+                };
+                let step = Step::Comparison {
+                    left,
+                    operator: bin_op.node.to_string(),
+                    right,
+                };
+                (outcome(result, span), step)
+            }
+            (
+                BooleanCondition::Match(_, match_op, pattern),
+                Node::Condition(Condition::Match(lval, _, regex), _),
+            ) => {
+                let value = self.match_value(lval, match_op.node, locals);
+                let step = Step::Match {
+                    value: value
+                        .as_ref()
+                        .ok()
+                        .map(|v| Value::from(v.clone().into_owned())),
+                    operator: match_op.node.to_string(),
+                    pattern: pattern.node.as_str().to_string(),
+                };
+                let result = value.and_then(|v| is_match(&v, match_op.node, regex));
+                (outcome(result, span), step)
+            }
+            (
+                BooleanCondition::Quantified(_, variable, _, body),
+                Node::Condition(Condition::Quantified(quantifier, collection, body_node), _),
+            ) => {
+                let (outcome, items) = match self.items(collection, locals) {
+                    Ok(items) => {
+                        self.trace_quantified(*quantifier, &items, body, body_node, locals)
                     }
-                    BinOp::SubSetOf => Ok(is_subset(&gv1, &gv2)),
-                    BinOp::SuperSetOf => Ok(is_super_set(&gv1, &gv2)),
-                    BinOp::IntersectionOf => Ok(intersection_of(&gv1, &gv2)),
-                    BinOp::NotIntersectionOf => Ok(not_intersection_of(&gv1, &gv2)),
+                    Err(kind) => (outcome(Err(kind), span), vec![]),
+                };
+                let step = Step::Quantified {
+                    variable: variable.node.clone(),
+                    items,
+                };
+                (outcome, step)
+            }
+            _ => unreachable!("a program mirrors its expression"),
+        };
+        Trace {
+            source: boolean_condition.node.to_string(),
+            span,
+            outcome,
+            step,
+        }
+    }
+
+    // Like `quantified`, keeping the trace of the body for each item
+    fn trace_quantified<'a>(
+        &'a self,
+        quantifier: Quantifier,
+        items: &'a [SimpleValue],
+        body: &BooleanExpression,
+        node: &'a Node,
+        locals: Option<&'a Locals<'a>>,
+    ) -> (Outcome, Vec<(Value, Trace)>) {
+        let (decisive, result) = decisive(quantifier);
+        let mut outcome = Outcome::Evaluated(!result);
+        let mut decided = false;
+        let mut traces = vec![];
+        for item in items {
+            let trace = match decided {
+                true => skipped_expression(body),
+                false => {
+                    let locals = Locals {
+                        item,
+                        parent: locals,
+                    };
+                    self.trace_expression(body, node, Some(&locals))
+                }
+            };
+            match &trace.outcome {
+                Outcome::Evaluated(b) if *b == decisive => {
+                    outcome = Outcome::Evaluated(result);
+                    decided = self.options.evaluation == Evaluation::ShortCircuit;
                 }
+                Outcome::Failed(_) => {
+                    outcome = trace.outcome.clone();
+                    decided = true;
+                }
+                _ => (),
             }
+            traces.push((Value::from(item.clone()), trace));
+        }
+        (outcome, traces)
+    }
+}
+
+// The value of an evaluated side of a comparison, with groups as lists
+fn operand_value(operand: &Operand) -> Value {
+    match operand {
+        Operand::Value(v) => Value::from(v.clone().into_owned()),
+        Operand::Group(items) => Value::List(items.iter().cloned().map(Value::from).collect()),
+    }
+}
+
+fn outcome(result: Result<bool, EvalErrorKind>, span: Span) -> Outcome {
+    match result {
+        Ok(b) => Outcome::Evaluated(b),
+        Err(kind) => Outcome::Failed(EvalError { kind, span }),
+    }
+}
+
+fn binary_step(and_or: AndOr, lhs: Trace, rhs: Trace) -> Step {
+    match and_or {
+        AndOr::And => Step::And(Box::new(lhs), Box::new(rhs)),
+        AndOr::Or => Step::Or(Box::new(lhs), Box::new(rhs)),
+    }
+}
+
+// The trace of a part that wasn't reached, which mirrors the part without
+// any values
+fn skipped_condition(boolean_condition: &Spanned<BooleanCondition>) -> Trace {
+    let step = match &boolean_condition.node {
+        BooleanCondition::Comparison(_, bin_op, _) => Step::Comparison {
+            left: None,
+            operator: bin_op.node.to_string(),
+            right: None,
         },
-        BooleanCondition::Group(boxed_expr) => eval_boolean_expression(&*boxed_expr, context),
+        BooleanCondition::Match(_, match_op, pattern) => Step::Match {
+            value: None,
+            operator: match_op.node.to_string(),
+            pattern: pattern.node.as_str().to_string(),
+        },
+        BooleanCondition::Quantified(_, variable, _, _) => Step::Quantified {
+            variable: variable.node.clone(),
+            items: vec![],
+        },
+        BooleanCondition::Group(expr) => Step::Group(Box::new(skipped_expression(expr))),
+        BooleanCondition::Not(cond) => Step::Not(Box::new(skipped_condition(cond))),
+    };
+    Trace {
+        source: boolean_condition.node.to_string(),
+        span: boolean_condition.span,
+        outcome: Outcome::Skipped,
+        step,
     }
 }
 
-fn eval_boolean_expression(
-    boolean_expression: &BooleanExpression,
-    context: &HashMap<Vec<&str>, SimpleValue>,
-) -> Result<bool, EvalError> {
-    let mut result = eval_boolean_condition(&boolean_expression.initial, context)?;
-    for (and_or, cond) in boolean_expression.conditions.as_slice() {
-        let next = eval_boolean_condition(&cond, context)?;
-        match and_or {
-            AndOr::And => {
-                result = result && next;
-            }
-            AndOr::Or => {
-                result = result || next;
+fn skipped_expression(boolean_expression: &BooleanExpression) -> Trace {
+    match boolean_expression {
+        BooleanExpression::Condition(cond) => skipped_condition(cond),
+        BooleanExpression::Binary(lhs, and_or, rhs) => {
+            let (lhs, rhs) = (skipped_expression(lhs), skipped_expression(rhs));
+            Trace {
+                source: boolean_expression.to_string(),
+                span: Span {
+                    start: lhs.span.start,
+                    end: rhs.span.end,
+                },
+                outcome: Outcome::Skipped,
+                step: binary_step(*and_or, lhs, rhs),
             }
         }
     }
-    return Ok(result);
 }
 
-pub fn eval(boolean_expression: &BooleanExpression) -> Result<bool, EvalError> {
-    return eval_boolean_expression(&boolean_expression, &HashMap::new());
+pub fn eval(program: &Program, options: &Options) -> Result<bool, EvalError> {
+    let context: HashMap<Vec<&str>, Value> = HashMap::new();
+    eval_with_context(program, &context, options)
 }
 
 pub fn eval_with_context(
+    program: &Program,
+    context: &dyn ContextProvider,
+    options: &Options,
+) -> Result<bool, EvalError> {
+    let truth = Frame::new(program, context, options, false).node(&program.root, None)?;
+    Ok(truth == Truth::True)
+}
+
+pub fn eval_with_unknowns(
+    program: &Program,
+    context: &dyn ContextProvider,
+    options: &Options,
+) -> Result<Truth, EvalError> {
+    Frame::new(program, context, options, true).node(&program.root, None)
+}
+
+pub fn explain(
     boolean_expression: &BooleanExpression,
-    context: &HashMap<Vec<&str>, SimpleValue>,
+    program: &Program,
+    context: &dyn ContextProvider,
+    options: &Options,
+) -> Trace {
+    let frame = Frame::new(program, context, options, false);
+    frame.trace_expression(boolean_expression, &program.root, None)
+}
+
+// Evaluates conditions one at a time with missing paths making them unknown,
+// sharing the paths resolved between them
+pub struct Conditions<'a> {
+    frame: Frame<'a>,
+    // The compiled conditions outside of quantifiers, by where they are in
+    // the expression
+    conditions: HashMap<Span, &'a Condition>,
+    pub options: &'a Options,
+}
+
+impl<'a> Conditions<'a> {
+    pub fn new(
+        program: &'a Program,
+        context: &'a dyn ContextProvider,
+        options: &'a Options,
+    ) -> Conditions<'a> {
+        fn collect<'a>(node: &'a Node, conditions: &mut HashMap<Span, &'a Condition>) {
+            match node {
+                Node::Condition(condition, span) => {
+                    conditions.insert(*span, condition);
+                }
+                Node::Not(node) => collect(node, conditions),
+                Node::Binary(lhs, _, rhs) => {
+                    collect(lhs, conditions);
+                    collect(rhs, conditions);
+                }
+            }
+        }
+        let mut conditions = HashMap::new();
+        collect(&program.root, &mut conditions);
+        Conditions {
+            frame: Frame::new(program, context, options, true),
+            conditions,
+            options,
+        }
+    }
+
+    pub fn eval(&self, condition: &Spanned<BooleanCondition>) -> Result<Truth, EvalError> {
+        let span = condition.span;
+        self.frame.condition(self.conditions[&span], span, None)
+    }
+}
+
+// Evaluates with the paths fetched so far, returning the slots of the paths
+// that are still needed to decide the result, if any
+fn eval_fetched(
+    program: &Program,
+    fetched: Fetched,
+    options: &Options,
+) -> (Result<bool, EvalError>, Fetched, Vec<usize>) {
+    let frame = Frame::fetched(program, fetched, options);
+    let result = frame
+        .node(&program.root, None)
+        .map(|truth| truth == Truth::True);
+    let fetched = Fetched {
+        slots: frame.slots,
+        decided: frame.decided.into_inner(),
+        called: frame.called.into_inner(),
+    };
+    (result, fetched, frame.pending.into_inner())
+}
+
+// Evaluates until a path that hasn't been fetched is reached, then fetches
+// every path reached so far together and evaluates again. Paths that are only
+// reached depending on another path's value are fetched in a later round.
+pub async fn eval_async<P: AsyncContextProvider + ?Sized>(
+    program: &Program,
+    provider: &P,
+    options: &Options,
 ) -> Result<bool, EvalError> {
-    return eval_boolean_expression(&boolean_expression, &context);
+    let mut fetched = Fetched {
+        slots: program.paths.iter().map(|_| OnceCell::new()).collect(),
+        decided: HashMap::new(),
+        called: vec![],
+    };
+    loop {
+        let (result, learnt, pending) = eval_fetched(program, fetched, options);
+        if pending.is_empty() {
+            return result;
+        }
+        fetched = learnt;
+        let paths: Vec<Vec<&str>> = pending
+            .iter()
+            .map(|slot| program.paths[*slot].iter().map(|s| s.as_str()).collect())
+            .collect();
+        let values = join_all(paths.iter().map(|path| provider.get(path))).await;
+        for (slot, value) in pending.into_iter().zip(values) {
+            let _ = fetched.slots[slot].set(value.as_ref().map(SimpleValue::from));
+        }
+    }
+}
+
+// Polls every future until they're all ready
+async fn join_all<F: Future>(futures: impl Iterator<Item = F>) -> Vec<F::Output> {
+    let mut futures: Vec<_> = futures.map(Box::pin).collect();
+    let mut outputs: Vec<Option<F::Output>> = futures.iter().map(|_| None).collect();
+    poll_fn(|cx| {
+        let mut ready = true;
+        for (future, output) in futures.iter_mut().zip(outputs.iter_mut()) {
+            if output.is_none() {
+                match future.as_mut().poll(cx) {
+                    Poll::Ready(value) => *output = Some(value),
+                    Poll::Pending => ready = false,
+                }
+            }
+        }
+        if ready {
+            Poll::Ready(outputs.iter_mut().map(|o| o.take().unwrap()).collect())
+        } else {
+            Poll::Pending
+        }
+    })
+    .await
 }
 
 #[test]
 fn test_eval() {
+    use crate::{compiler::compile, walker::eval_both};
+    let empty: HashMap<Vec<&str>, Value> = HashMap::new();
+
     let exprs = [
         ("5 > 3", true),
         ("5 < 3", false),
@@ -371,6 +1278,53 @@ fn test_eval() {
         ("(1, 2) not∩ (4, 5, 6)", true),
         ("(3) not∩ (3, 4, 5)", false),
         ("(3, 4) not∩ (3, 4, 5)", false),
+        ("not 5 > 3", false),
+        ("not (5 > 3 and 3 > 5)", true),
+        ("!(3) not∩ (3, 4, 5)", true),
+        ("¬ ¬ 1 = 1", true),
+        ("1 + 2 * 3 = 7", true),
+        ("(1 + 2) * 3 = 9", true),
+        ("10 - 4 - 3 = 3", true),
+        ("7 % 4 = 3", true),
+        ("1 / 4 = 0.25", true),
+        ("-(2 - 3) = 1", true),
+        ("2 * 3 in (5, 6)", true),
+        ("(1 + 1, 2 * 2) ⊆ (2, 4)", true),
+        ("len(\"abc\") = 3", true),
+        ("max(1, abs(-5), 3) = 5", true),
+        ("round(2.345, 2) = 2.35", true),
+        ("upper(trim(\" a \")) in (\"A\", \"B\")", true),
+        ("\"/api/v1\" starts_with \"/api\"", true),
+        ("\"report.csv\" ends_with \".txt\"", false),
+        ("not \"haystack\" contains \"needle\"", true),
+        ("(1, 2) contains 2", true),
+        ("\"abc123\" =~ \"^[a-z]+\\d+$\"", true),
+        ("\"abc\" matches \"^b\"", false),
+        ("\"abc\" !~ \"^b\"", true),
+        ("lower(\"ABC\") =~ \"abc\" and 1 = 1", true),
+        ("\"/api/v1/users\" like \"/api/%\"", true),
+        ("\"/web/api\" like \"/api/%\"", false),
+        ("\"abc\" like \"a_c\"", true),
+        ("\"abc\" like \"a_\"", false),
+        ("\"ABC\" like \"abc\"", false),
+        ("\"ABC\" ilike \"a%\"", true),
+        ("\"100%\" like \"%\\%\"", true),
+        ("\"100\" like \"%\\%\"", false),
+        ("\"a_b\" like \"a\\_b\"", true),
+        ("\"axb\" like \"a\\_b\"", false),
+        ("\"\" like \"%\"", true),
+        ("\"report.csv\" glob \"*.csv\"", true),
+        ("\"report.CSV\" glob \"*.csv\"", false),
+        ("\"report.CSV\" iglob \"*.csv\"", true),
+        ("\"a1\" glob \"?[0-9]\"", true),
+        ("\"ab\" glob \"?[!b]\"", false),
+        ("\"a*\" glob \"a\\*\"", true),
+        ("\"ab\" glob \"a\\*\"", false),
+        ("\"[a\" glob \"[a\"", true),
+        (
+            "\"aaaaaaaaaaaaaaaaaaaaaaaaaaaaab\" glob \"*a*a*a*a*a*a*a*a*c\"",
+            false,
+        ),
     ];
     let exprs_with_context = [
         (
@@ -413,39 +1367,438 @@ fn test_eval() {
         ("(a) == (a)", vec![("a", SimpleValue::Number(5.0))], true),
         ("(a) == 1", vec![("a", SimpleValue::Number(5.0))], false),
         ("1 == (a)", vec![("a", SimpleValue::Number(5.0))], false),
+        (
+            "order.total - order.discount > 100",
+            vec![
+                ("order.total", SimpleValue::Number(150.0)),
+                ("order.discount", SimpleValue::Number(20.0)),
+            ],
+            true,
+        ),
+        (
+            "score * 1.5 >= threshold",
+            vec![
+                ("score", SimpleValue::Number(10.0)),
+                ("threshold", SimpleValue::Number(15.0)),
+            ],
+            true,
+        ),
+        (
+            "lower(user.email) ends_with \"@corp.com\"",
+            vec![("user.email", SimpleValue::Str("Ada@Corp.COM".to_owned()))],
+            true,
+        ),
+        (
+            "len(name) > 3 and contains(name, \"bob\") = true",
+            vec![("name", SimpleValue::Str("bobby".to_owned()))],
+            true,
+        ),
+        (
+            "user.email =~ \"^[a-z]+@example\\.com$\"",
+            vec![("user.email", SimpleValue::Str("ada@example.com".to_owned()))],
+            true,
+        ),
+        (
+            "user.email =~ \"^[a-z]+@example\\.com$\"",
+            vec![("user.email", SimpleValue::Str("ada@exampleXcom".to_owned()))],
+            false,
+        ),
+        (
+            "user.roles ∩ (\"admin\", \"ops\") and \"admin\" notin user.roles",
+            vec![(
+                "user.roles",
+                SimpleValue::List(vec![
+                    SimpleValue::Str("dev".to_owned()),
+                    SimpleValue::Str("ops".to_owned()),
+                ]),
+            )],
+            true,
+        ),
+        (
+            "(\"dev\") ⊆ user.roles and user.roles ⊆ (\"dev\", \"ops\", \"qa\")",
+            vec![(
+                "user.roles",
+                SimpleValue::List(vec![
+                    SimpleValue::Str("dev".to_owned()),
+                    SimpleValue::Str("ops".to_owned()),
+                ]),
+            )],
+            true,
+        ),
+        (
+            "a ∩ b or a = (1, 2) or len(a) = 2",
+            vec![
+                (
+                    "a",
+                    SimpleValue::List(vec![SimpleValue::Number(1.0), SimpleValue::Number(2.0)]),
+                ),
+                ("b", SimpleValue::List(vec![SimpleValue::Number(3.0)])),
+            ],
+            true,
+        ),
+        (
+            "tags contains \"vip\" and contains(tags, \"new\") = false",
+            vec![(
+                "tags",
+                SimpleValue::List(vec![
+                    SimpleValue::Str("vip".to_owned()),
+                    SimpleValue::Str("eu".to_owned()),
+                ]),
+            )],
+            true,
+        ),
+        (
+            "x in empty or x = 1",
+            vec![
+                ("x", SimpleValue::Number(1.0)),
+                ("empty", SimpleValue::List(vec![])),
+            ],
+            true,
+        ),
+        (
+            "x in empty",
+            vec![
+                ("x", SimpleValue::Number(1.0)),
+                ("empty", SimpleValue::List(vec![])),
+            ],
+            false,
+        ),
     ];
 
     for (expr, test) in exprs.iter() {
-        let boolean_expression = crate::parser::parse(expr).unwrap();
-        let result = eval(&boolean_expression);
+        let boolean_expression = crate::parser::parse(expr, &Options::default()).unwrap();
+        let result = eval_both(&boolean_expression, &empty, &Options::default());
         assert!(result.unwrap() == *test, "{expr} should eval to {test}");
     }
     for (expr, ctx, test) in exprs_with_context.iter() {
-        let boolean_expression = crate::parser::parse(expr).unwrap();
-        let mut context: HashMap<Vec<&str>, SimpleValue> = HashMap::new();
+        let boolean_expression = crate::parser::parse(expr, &Options::default()).unwrap();
+        let mut context: HashMap<Vec<&str>, Value> = HashMap::new();
         for (k, v) in ctx {
-            context.insert(k.split('.').collect(), v.clone());
+            context.insert(k.split('.').collect(), Value::from(v.clone()));
         }
-        let result = eval_with_context(&boolean_expression, &context);
+        let result = eval_both(&boolean_expression, &context, &Options::default());
         assert!(result.unwrap() == *test, "{expr} should eval to {test}");
     }
 
-    // TODO: add better coverage for expected errors
-    match eval(&crate::parser::parse("true = a").unwrap()) {
-        Ok(_) => Err("expected error"),
-        Err(_) => Ok(()),
+    // (expression, the failing comparison, expected error)
+    let errors = [
+        (
+            "true = a",
+            "true = a",
+            EvalErrorKind::MissingVariable {
+                path: vec!["a".to_string()],
+            },
+        ),
+        (
+            "1 = 1 and (2 = 3 or foo.bar > 1)",
+            "foo.bar > 1",
+            EvalErrorKind::MissingVariable {
+                path: vec!["foo".to_string(), "bar".to_string()],
+            },
+        ),
+        (
+            "not 1 in 2",
+            "1 in 2",
+            EvalErrorKind::NotIterable {
+                value: "2".to_string(),
+            },
+        ),
+        (
+            "1 = 1 and 1 ⊆ (1, 2)",
+            "1 ⊆ (1, 2)",
+            EvalErrorKind::NotIterable {
+                value: "1".to_string(),
+            },
+        ),
+        (
+            "1 / (2 - 2) = 1",
+            "1 / (2 - 2) = 1",
+            EvalErrorKind::DivisionByZero,
+        ),
+        ("1 = 1 % 0", "1 = 1 % 0", EvalErrorKind::DivisionByZero),
+        (
+            "1 + \"a\" = 1",
+            "1 + \"a\" = 1",
+            EvalErrorKind::NonNumeric {
+                op: "+".to_string(),
+                value: "a".to_string(),
+            },
+        ),
+        (
+            "-true = 1",
+            "-true = 1",
+            EvalErrorKind::NonNumeric {
+                op: "-".to_string(),
+                value: "true".to_string(),
+            },
+        ),
+        (
+            "lower(1) = \"1\"",
+            "lower(1) = \"1\"",
+            EvalErrorKind::InvalidArgument {
+                function: "lower".to_string(),
+                value: "1".to_string(),
+            },
+        ),
+        (
+            "1 starts_with \"1\"",
+            "1 starts_with \"1\"",
+            EvalErrorKind::InvalidArgument {
+                function: "starts_with".to_string(),
+                value: "1".to_string(),
+            },
+        ),
+        (
+            "\"a\" contains (\"a\", \"b\")",
+            "\"a\" contains (\"a\", \"b\")",
+            EvalErrorKind::TypeMismatch {
+                op: "contains".to_string(),
+                left: "string".to_string(),
+                right: "group".to_string(),
+            },
+        ),
+        (
+            "1 = 1 and 12 =~ \"1\"",
+            "12 =~ \"1\"",
+            EvalErrorKind::TypeMismatch {
+                op: "=~".to_string(),
+                left: "number".to_string(),
+                right: "pattern".to_string(),
+            },
+        ),
+        (
+            "1 like \"1%\"",
+            "1 like \"1%\"",
+            EvalErrorKind::TypeMismatch {
+                op: "like".to_string(),
+                left: "number".to_string(),
+                right: "string".to_string(),
+            },
+        ),
+        (
+            "\"a\" glob (\"a\", \"b\")",
+            "\"a\" glob (\"a\", \"b\")",
+            EvalErrorKind::TypeMismatch {
+                op: "glob".to_string(),
+                left: "string".to_string(),
+                right: "group".to_string(),
+            },
+        ),
+    ];
+    for (expr, failing, kind) in errors.iter() {
+        let boolean_expression = crate::parser::parse(expr, &Options::default()).unwrap();
+        let err = eval_both(&boolean_expression, &empty, &Options::default()).unwrap_err();
+        assert_eq!(err.kind(), kind, "{expr}");
+        assert_eq!(&expr[err.span().start..err.span().end], *failing, "{expr}");
     }
-    .unwrap();
+
+    // Strict comparisons pass for values of the same type and fail otherwise
+    let strict = Options {
+        comparison: Comparison::Strict,
+        ..Default::default()
+    };
+    let exprs = [
+        (
+            "1 < 2 and \"a\" < \"b\" and 1 = 1 and \"a\" != \"b\"",
+            Ok(true),
+        ),
+        ("none is none and 1 isnot none and none != \"a\"", Ok(true)),
+        ("1 in (1, \"a\") and (1, 2) = (1, 2)", Ok(true)),
+        ("true > false", Ok(true)),
+        ("7 == true", Err(("=", "number", "bool"))),
+        ("1 != \"1\"", Err(("!=", "number", "string"))),
+        ("\"thirty\" < 40", Err(("<", "string", "number"))),
+        ("1 >= none", Err((">=", "number", "none"))),
+        ("1 is true", Err(("is", "number", "bool"))),
+        ("1 = (1, 2)", Err(("=", "number", "group"))),
+    ];
+    for (expr, expected) in exprs.iter() {
+        let boolean_expression = crate::parser::parse(expr, &strict).unwrap();
+        let result = eval_both(&boolean_expression, &empty, &strict).map_err(|e| e.kind().clone());
+        let expected = expected.map_err(|(op, left, right)| EvalErrorKind::TypeMismatch {
+            op: op.to_string(),
+            left: left.to_string(),
+            right: right.to_string(),
+        });
+        assert_eq!(result, expected, "{expr}");
+    }
+
+    // Missing paths make conditions unknown rather than failing
+    let context = HashMap::from([
+        (vec!["a"], Value::Number(1.0)),
+        (
+            vec!["xs"],
+            Value::List(vec![Value::Number(1.0), Value::Number(2.0)]),
+        ),
+    ]);
+    let exprs = [
+        ("a = 1", Truth::True),
+        ("missing = 1", Truth::Unknown),
+        ("a.b = 1", Truth::Unknown),
+        ("a = 1 and missing = 1", Truth::Unknown),
+        ("a = 2 and missing = 1", Truth::False),
+        ("missing = 1 and a = 2", Truth::False),
+        ("missing = 1 or a = 1", Truth::True),
+        ("missing = 1 or a = 2", Truth::Unknown),
+        ("not missing = 1", Truth::Unknown),
+        ("not (missing = 1 and a = 2)", Truth::True),
+        ("missing =~ \"x\"", Truth::Unknown),
+        ("any(x in xs: x = missing)", Truth::Unknown),
+        ("any(x in xs: x = 2 or x = missing)", Truth::True),
+        ("all(x in xs: x = 1 and missing = 1)", Truth::False),
+        ("all(x in xs: x < 3 and missing = 1)", Truth::Unknown),
+        ("none(x in missing: x = 1)", Truth::Unknown),
+    ];
+    for evaluation in [Evaluation::ShortCircuit, Evaluation::Eager] {
+        let options = Options {
+            evaluation,
+            ..Default::default()
+        };
+        for (expr, expected) in exprs.iter() {
+            let boolean_expression = crate::parser::parse(expr, &options).unwrap();
+            let result = eval_with_unknowns(&compile(&boolean_expression), &context, &options);
+            assert_eq!(result, Ok(*expected), "{expr}");
+        }
+    }
+    let boolean_expression =
+        crate::parser::parse("a / 0 = 1 or missing = 1", &Options::default()).unwrap();
+    let result = eval_with_unknowns(&compile(&boolean_expression), &context, &Options::default());
+    assert_eq!(result.unwrap_err().kind(), &EvalErrorKind::DivisionByZero);
 }
 
-#[derive(Debug)]
+/// What went wrong while evaluating a comparison.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EvalErrorKind {
+    /// The property path isn't in the context, e.g. `["foo", "bar"]` for `foo.bar`.
+    MissingVariable { path: Vec<String> },
+    /// The context holds a value for the path that can't be compared.
+    InvalidContextValue { path: Vec<String> },
+    /// An operator that needs a group of values was given a single value.
+    NotIterable { value: String },
+    /// An index like `items[3]` is past the end of a list with `len` items.
+    IndexOutOfRange {
+        path: Vec<String>,
+        index: i64,
+        len: usize,
+    },
+    /// An arithmetic operator was applied to something other than a number.
+    NonNumeric { op: String, value: String },
+    /// The right-hand side of `/` or `%` was zero.
+    DivisionByZero,
+    /// A function was called with an argument it doesn't support.
+    InvalidArgument { function: String, value: String },
+    /// A registered function returned an error.
+    FunctionFailed { function: String, message: String },
+    /// An operator was applied to operands of types it doesn't support.
+    TypeMismatch {
+        op: String,
+        left: String,
+        right: String,
+    },
+}
+
+/// An error raised while evaluating an expression, pointing at the
+/// comparison that failed.
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct EvalError {
-    message: String,
+    pub(crate) kind: EvalErrorKind,
+    pub(crate) span: Span,
+}
+impl EvalError {
+    pub fn kind(&self) -> &EvalErrorKind {
+        &self.kind
+    }
+
+    /// The span of the comparison that failed in the source expression.
+    pub fn span(&self) -> Span {
+        self.span
+    }
 }
 impl Error for EvalError {}
 impl fmt::Display for EvalError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{0}", self.message)
+        write!(f, "{0}", self.kind)
+    }
+}
+impl fmt::Display for EvalErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EvalErrorKind::MissingVariable { path } => {
+                write!(f, "{} missing from context", path.join("."))
+            }
+            EvalErrorKind::InvalidContextValue { path } => {
+                write!(f, "{} has an invalid value in the context", path.join("."))
+            }
+            EvalErrorKind::NotIterable { value } => write!(f, "{value} is not iterable"),
+            EvalErrorKind::IndexOutOfRange { path, index, len } => write!(
+                f,
+                "index {index} is out of range for {}, which has {len} items",
+                path.join(".")
+            ),
+            EvalErrorKind::NonNumeric { op, value } => {
+                write!(f, "can't apply `{op}` to {value}, it isn't a number")
+            }
+            EvalErrorKind::DivisionByZero => write!(f, "division by zero"),
+            EvalErrorKind::InvalidArgument { function, value } => {
+                write!(f, "`{function}` can't be applied to {value}")
+            }
+            EvalErrorKind::FunctionFailed { function, message } => {
+                write!(f, "`{function}` failed: {message}")
+            }
+            EvalErrorKind::TypeMismatch { op, left, right } => {
+                write!(f, "can't apply `{op}` to {left} and {right}")
+            }
+        }
+    }
+}
+
+impl fmt::Display for BinOp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let op = match self {
+            BinOp::Equal => "=",
+            BinOp::NotEqual => "!=",
+            BinOp::GreaterThan => ">",
+            BinOp::GreaterThanOrEqual => ">=",
+            BinOp::LessThan => "<",
+            BinOp::LessThanOrEqual => "<=",
+            BinOp::In => "in",
+            BinOp::NotIn => "notin",
+            BinOp::Is => "is",
+            BinOp::IsNot => "isnot",
+            BinOp::SubSetOf => "⊆",
+            BinOp::SuperSetOf => "⊇",
+            BinOp::IntersectionOf => "∩",
+            BinOp::NotIntersectionOf => "not∩",
+            BinOp::StartsWith => "starts_with",
+            BinOp::EndsWith => "ends_with",
+            BinOp::Contains => "contains",
+            BinOp::Like => "like",
+            BinOp::ILike => "ilike",
+            BinOp::Glob => "glob",
+            BinOp::IGlob => "iglob",
+        };
+        write!(f, "{op}")
+    }
+}
+
+impl fmt::Display for MatchOp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MatchOp::Matches => write!(f, "=~"),
+            MatchOp::NotMatches => write!(f, "!~"),
+        }
+    }
+}
+
+impl fmt::Display for ArithOp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ArithOp::Add => write!(f, "+"),
+            ArithOp::Sub => write!(f, "-"),
+            ArithOp::Mul => write!(f, "*"),
+            ArithOp::Div => write!(f, "/"),
+            ArithOp::Rem => write!(f, "%"),
+        }
     }
 }
 
@@ -456,10 +1809,31 @@ impl fmt::Display for SimpleValue {
             SimpleValue::Str(s) => write!(f, "{s}"),
             SimpleValue::Bool(b) => write!(f, "{b}"),
             SimpleValue::None => write!(f, "none"),
+            SimpleValue::List(items) => {
+                let items: Vec<_> = items.iter().map(|item| item.to_string()).collect();
+                write!(f, "[{}]", items.join(", "))
+            }
+            SimpleValue::Map(map) => {
+                let entries: Vec<_> = map.iter().map(|(k, v)| format!("{k}: {v}")).collect();
+                write!(f, "{{{}}}", entries.join(", "))
+            }
             SimpleValue::PropertyPath(p) => write!(f, "{p:?}"),
         }
     }
 }
+impl SimpleValue {
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            SimpleValue::Number(_) => "number",
+            SimpleValue::Str(_) => "string",
+            SimpleValue::Bool(_) => "bool",
+            SimpleValue::None => "none",
+            SimpleValue::List(_) => "list",
+            SimpleValue::Map(_) => "map",
+            SimpleValue::PropertyPath(_) => "property path",
+        }
+    }
+}
 impl PartialEq for SimpleValue {
     fn eq(&self, other: &Self) -> bool {
         match (self, other) {
@@ -467,6 +1841,8 @@ impl PartialEq for SimpleValue {
             (SimpleValue::Str(s1), SimpleValue::Str(s2)) => s1 == s2,
             (SimpleValue::Bool(b1), SimpleValue::Bool(b2)) => b1 == b2,
             (SimpleValue::None, SimpleValue::None) => true,
+            (SimpleValue::List(l1), SimpleValue::List(l2)) => l1 == l2,
+            (SimpleValue::Map(m1), SimpleValue::Map(m2)) => m1 == m2,
             _ => false,
         }
     }
@@ -496,27 +1872,142 @@ impl Hash for SimpleValue {
                 bool_val.hash(hasher);
             }
             SimpleValue::None => hasher.write_u64(0),
+            SimpleValue::List(items) => items.hash(hasher),
+            SimpleValue::Map(map) => map.hash(hasher),
             SimpleValue::PropertyPath(_) => panic!("property paths can't be hashed"),
         }
     }
 }
 
-fn is_subset<T: Eq + std::hash::Hash>(subset: &Vec<T>, superset: &Vec<T>) -> bool {
+// A piece of a `like` or `glob` pattern
+#[derive(Debug, PartialEq)]
+enum Wildcard {
+    // Matches any run of characters: `%` or `*`
+    Many,
+    // Matches a single character: `_` or `?`
+    One,
+    // `[a-z0-9]`, or `[!a-z]` when negated
+    Class(bool, Vec<(char, char)>),
+    Literal(char),
+}
+
+// A backslash escapes the next character. An unclosed `[` or a trailing
+// backslash is matched literally.
+fn wildcards(pattern: &str, glob: bool) -> Vec<Wildcard> {
+    let chars: Vec<char> = pattern.chars().collect();
+    let mut wildcards = vec![];
+    let mut i = 0;
+    while i < chars.len() {
+        let wildcard = match chars[i] {
+            '\\' if i + 1 < chars.len() => {
+                i += 1;
+                Wildcard::Literal(chars[i])
+            }
+            '%' if !glob => Wildcard::Many,
+            '_' if !glob => Wildcard::One,
+            '*' if glob => Wildcard::Many,
+            '?' if glob => Wildcard::One,
+            '[' if glob => match class(&chars[i + 1..]) {
+                Some((wildcard, len)) => {
+                    i += len;
+                    wildcard
+                }
+                None => Wildcard::Literal('['),
+            },
+            c => Wildcard::Literal(c),
+        };
+        wildcards.push(wildcard);
+        i += 1;
+    }
+    wildcards
+}
+
+// Parses the inside of `[...]`, returning the class and how many characters it used
+fn class(chars: &[char]) -> Option<(Wildcard, usize)> {
+    let negated = matches!(chars.first(), Some('!' | '^'));
+    let mut i = usize::from(negated);
+    let mut ranges = vec![];
+    // A `]` straight after the opening bracket is part of the class
+    while i < chars.len() && (chars[i] != ']' || i == usize::from(negated)) {
+        let start = chars[i];
+        if chars.get(i + 1) == Some(&'-') && chars.get(i + 2).is_some_and(|c| *c != ']') {
+            ranges.push((start, chars[i + 2]));
+            i += 3;
+        } else {
+            ranges.push((start, start));
+            i += 1;
+        }
+    }
+    (i < chars.len()).then_some((Wildcard::Class(negated, ranges), i + 1))
+}
+
+fn wildcard_matches_char(wildcard: &Wildcard, c: char) -> bool {
+    match wildcard {
+        Wildcard::Many | Wildcard::One => true,
+        Wildcard::Class(negated, ranges) => {
+            ranges
+                .iter()
+                .any(|(start, end)| (*start..=*end).contains(&c))
+                != *negated
+        }
+        Wildcard::Literal(literal) => *literal == c,
+    }
+}
+
+// Matches `s` against a `like`, `ilike`, `glob` or `iglob` pattern. Backtracks
+// only to the most recent `Many`, so this takes at most O(len(s) * len(pattern)).
+fn wildcard_match(bin_op: BinOp, s: &str, pattern: &str) -> bool {
+    let (s, pattern) = match bin_op {
+        BinOp::ILike | BinOp::IGlob => (s.to_lowercase(), pattern.to_lowercase()),
+        _ => (s.to_string(), pattern.to_string()),
+    };
+    let wildcards = wildcards(&pattern, matches!(bin_op, BinOp::Glob | BinOp::IGlob));
+    let chars: Vec<char> = s.chars().collect();
+    let (mut w, mut c) = (0, 0);
+    // The last `Many` seen and the position in `s` it's currently matched up to
+    let mut backtrack = None;
+    while c < chars.len() {
+        match wildcards.get(w) {
+            Some(Wildcard::Many) => {
+                backtrack = Some((w, c));
+                w += 1;
+            }
+            Some(wildcard) if wildcard_matches_char(wildcard, chars[c]) => {
+                w += 1;
+                c += 1;
+            }
+            _ => match backtrack {
+                // Let the `Many` swallow one more character and try again
+                Some((many, matched)) => {
+                    backtrack = Some((many, matched + 1));
+                    w = many + 1;
+                    c = matched + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    wildcards[w..]
+        .iter()
+        .all(|wildcard| *wildcard == Wildcard::Many)
+}
+
+fn is_subset<T: Eq + std::hash::Hash>(subset: &[T], superset: &[T]) -> bool {
     let superset_set: HashSet<_> = superset.iter().collect();
     subset.iter().all(|item| superset_set.contains(item))
 }
 
-fn is_super_set<T: Eq + std::hash::Hash>(superset: &Vec<T>, subset: &Vec<T>) -> bool {
+fn is_super_set<T: Eq + std::hash::Hash>(superset: &[T], subset: &[T]) -> bool {
     is_subset(subset, superset)
 }
 
-fn intersection_of<T: Eq + std::hash::Hash>(vec1: &Vec<T>, vec2: &Vec<T>) -> bool {
+fn intersection_of<T: Eq + std::hash::Hash>(vec1: &[T], vec2: &[T]) -> bool {
     let set1: HashSet<_> = vec1.iter().collect();
     let set2: HashSet<_> = vec2.iter().collect();
     set1.intersection(&set2).count() > 0
 }
 
-fn not_intersection_of<T: Eq + std::hash::Hash>(vec1: &Vec<T>, vec2: &Vec<T>) -> bool {
+fn not_intersection_of<T: Eq + std::hash::Hash>(vec1: &[T], vec2: &[T]) -> bool {
     let set1: HashSet<_> = vec1.iter().collect();
     let set2: HashSet<_> = vec2.iter().collect();
     set1.intersection(&set2).count() == 0
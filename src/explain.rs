@@ -0,0 +1,296 @@
+use crate::{parser::SimpleValue, EvalError, Span, Value};
+use std::fmt;
+
+/// How part of a rule was evaluated by
+/// [`CoolRule::explain`](crate::CoolRule::explain). Traces nest the same way
+/// as the rule, so the trace of `a = 1 and b = 2` holds the traces of `a = 1`
+/// and `b = 2`.
+///
+/// Its `Display` renders the whole tree as indented text, one part per line.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Trace {
+    /// The part of the rule, written out as it would be in an expression.
+    pub source: String,
+    /// Where the part is in the source expression.
+    pub span: Span,
+    /// What the part evaluated to.
+    pub outcome: Outcome,
+    /// How the part was evaluated, including the traces of its own parts.
+    pub step: Step,
+}
+
+/// What part of a rule evaluated to.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Outcome {
+    Evaluated(bool),
+    /// Evaluating the part failed, which fails the rule.
+    Failed(EvalError),
+    /// The part wasn't evaluated because the result was already decided,
+    /// e.g. the right of `false and ...` or the items after the first match
+    /// of `any`.
+    Skipped,
+}
+
+/// How part of a rule was evaluated.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Step {
+    And(Box<Trace>, Box<Trace>),
+    Or(Box<Trace>, Box<Trace>),
+    Not(Box<Trace>),
+    /// A parenthesised part of the rule.
+    Group(Box<Trace>),
+    /// A comparison along with the values of its sides, which are `None` if
+    /// they weren't evaluated. A group of values, or a path that holds a list,
+    /// is a [`Value::List`].
+    Comparison {
+        left: Option<Value>,
+        operator: String,
+        right: Option<Value>,
+    },
+    /// A regular expression match along with the value it was matched against.
+    Match {
+        value: Option<Value>,
+        operator: String,
+        pattern: String,
+    },
+    /// A quantifier like `any(x in xs: ...)` along with each item of the
+    /// collection and the trace of the condition for that item.
+    Quantified {
+        variable: String,
+        items: Vec<(Value, Trace)>,
+    },
+}
+
+// Values are written the way they would be in an expression
+fn value(v: &Value) -> String {
+    match v {
+        Value::Str(s) => format!("\"{s}\""),
+        Value::List(items) => {
+            let items: Vec<_> = items.iter().map(value).collect();
+            format!("({})", items.join(", "))
+        }
+        Value::Map(map) => {
+            let mut entries: Vec<_> = map
+                .iter()
+                .map(|(k, v)| format!("{k}: {}", value(v)))
+                .collect();
+            entries.sort();
+            format!("{{{}}}", entries.join(", "))
+        }
+        v => SimpleValue::from(v).to_string(),
+    }
+}
+
+// Each part is a line with its outcome, indented under the part it's in.
+// Skipped parts aren't broken down any further.
+fn render(trace: &Trace, depth: usize, f: &mut fmt::Formatter) -> fmt::Result {
+    let indent = "  ".repeat(depth);
+    let outcome = match &trace.outcome {
+        Outcome::Evaluated(b) => b.to_string(),
+        Outcome::Failed(_) => "error".to_string(),
+        Outcome::Skipped => "skipped".to_string(),
+    };
+    let what = match &trace.step {
+        _ if trace.outcome == Outcome::Skipped => trace.source.clone(),
+        Step::And(..) => "and".to_string(),
+        Step::Or(..) => "or".to_string(),
+        Step::Not(_) => "not".to_string(),
+        // The parentheses don't change the outcome, so only their contents
+        // get a line
+        Step::Group(inner) => return render(inner, depth, f),
+        Step::Comparison {
+            left: Some(left),
+            operator,
+            right: Some(right),
+        } => format!(
+            "{} ({} {operator} {})",
+            trace.source,
+            value(left),
+            value(right)
+        ),
+        Step::Match {
+            value: Some(v),
+            operator,
+            pattern,
+        } => format!("{} ({} {operator} \"{pattern}\")", trace.source, value(v)),
+        Step::Comparison { .. } | Step::Match { .. } | Step::Quantified { .. } => {
+            trace.source.clone()
+        }
+    };
+    match &trace.outcome {
+        Outcome::Failed(e) => writeln!(f, "{indent}{outcome}: {what} ({e})")?,
+        _ => writeln!(f, "{indent}{outcome}: {what}")?,
+    }
+    if trace.outcome == Outcome::Skipped {
+        return Ok(());
+    }
+    match &trace.step {
+        Step::And(lhs, rhs) | Step::Or(lhs, rhs) => {
+            render(lhs, depth + 1, f)?;
+            render(rhs, depth + 1, f)
+        }
+        Step::Not(inner) | Step::Group(inner) => render(inner, depth + 1, f),
+        Step::Quantified { variable, items } => {
+            for (item, body) in items {
+                writeln!(f, "{indent}  with {variable} = {}", value(item))?;
+                render(body, depth + 2, f)?;
+            }
+            Ok(())
+        }
+        Step::Comparison { .. } | Step::Match { .. } => Ok(()),
+    }
+}
+
+impl fmt::Display for Trace {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        render(self, 0, f)
+    }
+}
+
+#[test]
+fn test_explain() {
+    use crate::{new, EvalErrorKind, Evaluation};
+    use std::collections::HashMap;
+
+    let context = HashMap::from([
+        (vec!["region"], Value::Str("eu".to_string())),
+        (vec!["plan"], Value::Str("free".to_string())),
+        (vec!["amount"], Value::Number(250.0)),
+        (
+            vec!["xs"],
+            Value::List(vec![
+                Value::Number(1.0),
+                Value::Number(2.0),
+                Value::Number(3.0),
+            ]),
+        ),
+    ]);
+    let exprs = [
+        (
+            "region = \"eu\" and (plan = \"pro\" or amount < 100)",
+            "\
+false: and
+  true: region = \"eu\" (\"eu\" = \"eu\")
+  false: or
+    false: plan = \"pro\" (\"free\" = \"pro\")
+    false: amount < 100 (250 < 100)
+",
+        ),
+        (
+            "region = \"us\" and (plan = \"pro\" or amount < 100)",
+            "\
+false: and
+  false: region = \"us\" (\"eu\" = \"us\")
+  skipped: (plan = \"pro\" or amount < 100)
+",
+        ),
+        (
+            "not region in (\"us\", \"ca\") or amount / 0 > 1",
+            "\
+true: or
+  true: not
+    false: region in (\"us\", \"ca\") (\"eu\" in (\"us\", \"ca\"))
+  skipped: amount / 0 > 1
+",
+        ),
+        (
+            "any(x in xs: x * 100 > amount) and plan =~ \"^f\"",
+            "\
+true: and
+  true: any(x in xs: x * 100 > amount)
+    with x = 1
+      false: x * 100 > amount (100 > 250)
+    with x = 2
+      false: x * 100 > amount (200 > 250)
+    with x = 3
+      true: x * 100 > amount (300 > 250)
+  true: plan =~ \"^f\" (\"free\" =~ \"^f\")
+",
+        ),
+        (
+            "all(x in xs: x < 2) or xs ⊆ (1, 2)",
+            "\
+false: or
+  false: all(x in xs: x < 2)
+    with x = 1
+      true: x < 2 (1 < 2)
+    with x = 2
+      false: x < 2 (2 < 2)
+    with x = 3
+      skipped: x < 2
+  false: xs ⊆ (1, 2) ((1, 2, 3) ⊆ (1, 2))
+",
+        ),
+        (
+            "plan = \"free\" and amount / 0 > 1 and region = \"eu\"",
+            "\
+error: and (division by zero)
+  error: and (division by zero)
+    true: plan = \"free\" (\"free\" = \"free\")
+    error: amount / 0 > 1 (division by zero)
+  skipped: region = \"eu\"
+",
+        ),
+        (
+            "missing = 1",
+            "error: missing = 1 (missing missing from context)\n",
+        ),
+    ];
+    for (expr, expected) in exprs.iter() {
+        let rule = new(expr).unwrap();
+        let trace = rule.explain(&context);
+        assert_eq!(trace.to_string(), *expected, "{expr}");
+        // The trace reaches the same result as evaluating the rule
+        match (trace.outcome, rule.test_with_context(&context)) {
+            (Outcome::Evaluated(b), Ok(result)) => assert_eq!(b, result, "{expr}"),
+            (Outcome::Failed(e), Err(crate::CoolRuleError::EvalError(error))) => {
+                assert_eq!(e, error, "{expr}")
+            }
+            (outcome, result) => panic!("{expr}: {outcome:?} but {result:?}"),
+        }
+    }
+
+    // Nothing is skipped when evaluating eagerly
+    let mut rule = new("region = \"us\" and amount > 1").unwrap();
+    rule.set_evaluation(Evaluation::Eager);
+    assert_eq!(
+        rule.explain(&context).to_string(),
+        "\
+false: and
+  false: region = \"us\" (\"eu\" = \"us\")
+  true: amount > 1 (250 > 1)
+"
+    );
+
+    let trace = new("(plan = \"pro\") or amount = -1")
+        .unwrap()
+        .explain(&context);
+    assert_eq!(trace.source, "(plan = \"pro\") or amount = -1");
+    assert_eq!(trace.span, Span { start: 0, end: 29 });
+    let Step::Or(lhs, rhs) = trace.step else {
+        panic!("expected or, got {:?}", trace.step);
+    };
+    let Step::Group(inner) = lhs.step else {
+        panic!("expected a group, got {:?}", lhs.step);
+    };
+    assert_eq!(inner.span, Span { start: 1, end: 13 });
+    assert_eq!(
+        rhs.step,
+        Step::Comparison {
+            left: Some(Value::Number(250.0)),
+            operator: "=".to_string(),
+            right: Some(Value::Number(-1.0)),
+        }
+    );
+
+    let trace = new("xs > 1").unwrap().explain(&context);
+    let Outcome::Failed(e) = trace.outcome else {
+        panic!("expected an error, got {:?}", trace.outcome);
+    };
+    assert_eq!(
+        e.kind(),
+        &EvalErrorKind::NotIterable {
+            value: "1".to_string()
+        }
+    );
+}
@@ -0,0 +1,506 @@
+use crate::{evaluator::EvalErrorKind, parser::SimpleValue, Value};
+use std::{collections::HashMap, fmt, ops::RangeInclusive, sync::Arc};
+
+/// The type of a value passed to or returned from a registered function.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Type {
+    Number,
+    Str,
+    Bool,
+    None,
+    List,
+    Map,
+    /// Any of the above.
+    Any,
+}
+
+impl Type {
+    pub(crate) fn of(value: &SimpleValue) -> Type {
+        match value {
+            SimpleValue::Number(_) => Type::Number,
+            SimpleValue::Str(_) => Type::Str,
+            SimpleValue::Bool(_) => Type::Bool,
+            SimpleValue::None | SimpleValue::PropertyPath(_) => Type::None,
+            SimpleValue::List(_) => Type::List,
+            SimpleValue::Map(_) => Type::Map,
+        }
+    }
+
+    // Whether a value of type `other` can be passed where `self` is expected
+    pub fn accepts(&self, other: Type) -> bool {
+        *self == Type::Any || *self == other
+    }
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Type::Number => write!(f, "number"),
+            Type::Str => write!(f, "string"),
+            Type::Bool => write!(f, "bool"),
+            Type::None => write!(f, "none"),
+            Type::List => write!(f, "list"),
+            Type::Map => write!(f, "map"),
+            Type::Any => write!(f, "any value"),
+        }
+    }
+}
+
+/// A function that can be called from expressions, e.g. `lower(user.email)`.
+#[derive(Debug)]
+pub struct Builtin {
+    pub name: &'static str,
+    // How many arguments the function accepts
+    pub arity: RangeInclusive<usize>,
+    pub returns: Type,
+    pub call: fn(&[SimpleValue]) -> Result<SimpleValue, EvalErrorKind>,
+}
+
+type HostFn = dyn Fn(&[Value]) -> Result<Value, String> + Send + Sync;
+
+// A function registered by the host application
+pub struct HostFunction {
+    pub name: String,
+    pub params: Vec<Type>,
+    pub returns: Type,
+    call: Box<HostFn>,
+}
+
+/// Functions registered by the host application, made callable from
+/// expressions by passing them to [`new_with_options`](crate::new_with_options)
+/// in [`Options::functions`](crate::Options::functions).
+///
+/// Calls are checked when the expression is parsed: unknown functions, the
+/// wrong number of arguments and arguments whose type is known up front (like
+/// literals or the results of other functions) are reported as parse errors.
+/// Arguments that come from the context are checked when the rule is tested.
+/// A registered function shadows a built-in one with the same name.
+///
+/// # Examples
+///
+/// ```
+/// use coolrule::{Functions, Options, Type, Value};
+///
+/// let mut functions = Functions::new();
+/// functions.register("is_weekend", &[Type::Str], Type::Bool, |args| match &args[0] {
+///     Value::Str(day) => Ok(Value::Bool(day == "sat" || day == "sun")),
+///     _ => unreachable!("arguments are checked before the call"),
+/// });
+/// let options = Options {
+///     functions,
+///     ..Default::default()
+/// };
+///
+/// let expr = coolrule::new_with_options("is_weekend(\"sun\") = true", options.clone()).unwrap();
+/// assert!(expr.test().unwrap());
+/// assert!(coolrule::new_with_options("is_weekend(1) = true", options).is_err());
+/// ```
+#[derive(Clone, Default)]
+pub struct Functions {
+    functions: HashMap<String, Arc<HostFunction>>,
+}
+
+impl Functions {
+    pub fn new() -> Functions {
+        Functions::default()
+    }
+
+    /// Registers `call` as `name`, taking one argument per entry in `params`
+    /// and returning a value of type `returns`. An error returned by `call`, or
+    /// a value of another type, fails the evaluation with
+    /// [`EvalErrorKind::FunctionFailed`].
+    pub fn register<F>(&mut self, name: &str, params: &[Type], returns: Type, call: F)
+    where
+        F: Fn(&[Value]) -> Result<Value, String> + Send + Sync + 'static,
+    {
+        self.functions.insert(
+            name.to_string(),
+            Arc::new(HostFunction {
+                name: name.to_string(),
+                params: params.to_vec(),
+                returns,
+                call: Box::new(call),
+            }),
+        );
+    }
+}
+
+impl fmt::Debug for Functions {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut names: Vec<_> = self.functions.keys().collect();
+        names.sort();
+        f.debug_set().entries(names).finish()
+    }
+}
+
+// A built-in or registered function that a call has been resolved to
+#[derive(Clone)]
+pub enum Function {
+    Builtin(&'static Builtin),
+    Host(Arc<HostFunction>),
+}
+
+impl Function {
+    // Registered functions take priority over built-ins
+    pub fn resolve(name: &str, functions: &Functions) -> Option<Function> {
+        match functions.functions.get(name) {
+            Some(host) => Some(Function::Host(host.clone())),
+            None => builtin(name).map(Function::Builtin),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        match self {
+            Function::Builtin(builtin) => builtin.name,
+            Function::Host(host) => &host.name,
+        }
+    }
+
+    pub fn arity(&self) -> RangeInclusive<usize> {
+        match self {
+            Function::Builtin(builtin) => builtin.arity.clone(),
+            Function::Host(host) => host.params.len()..=host.params.len(),
+        }
+    }
+
+    // The declared type of each argument, if any
+    pub fn params(&self) -> &[Type] {
+        match self {
+            Function::Builtin(_) => &[],
+            Function::Host(host) => &host.params,
+        }
+    }
+
+    pub fn returns(&self) -> Type {
+        match self {
+            Function::Builtin(builtin) => builtin.returns,
+            Function::Host(host) => host.returns,
+        }
+    }
+
+    pub fn call(&self, args: &[SimpleValue]) -> Result<SimpleValue, EvalErrorKind> {
+        let host = match self {
+            Function::Builtin(builtin) => return (builtin.call)(args),
+            Function::Host(host) => host,
+        };
+        let args = args
+            .iter()
+            .zip(&host.params)
+            .map(|(arg, param)| match param.accepts(Type::of(arg)) {
+                true => Ok(Value::from(arg.clone())),
+                false => Err(invalid_argument(&host.name, arg)),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let message = match (host.call)(&args) {
+            // Calls were type checked against `returns` when parsing, so a
+            // value of another type can't be let through
+            Ok(value) => {
+                let value = SimpleValue::from(&value);
+                let ty = Type::of(&value);
+                if host.returns.accepts(ty) {
+                    return Ok(value);
+                }
+                format!("returned a {ty} instead of a {}", host.returns)
+            }
+            Err(message) => message,
+        };
+        Err(EvalErrorKind::FunctionFailed {
+            function: host.name.clone(),
+            message,
+        })
+    }
+}
+
+impl fmt::Debug for Function {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+const BUILTINS: &[Builtin] = &[
+    Builtin {
+        name: "len",
+        arity: 1..=1,
+        returns: Type::Number,
+        call: len,
+    },
+    Builtin {
+        name: "lower",
+        arity: 1..=1,
+        returns: Type::Str,
+        call: lower,
+    },
+    Builtin {
+        name: "upper",
+        arity: 1..=1,
+        returns: Type::Str,
+        call: upper,
+    },
+    Builtin {
+        name: "trim",
+        arity: 1..=1,
+        returns: Type::Str,
+        call: trim,
+    },
+    Builtin {
+        name: "abs",
+        arity: 1..=1,
+        returns: Type::Number,
+        call: abs,
+    },
+    Builtin {
+        name: "min",
+        arity: 1..=usize::MAX,
+        returns: Type::Any,
+        call: min,
+    },
+    Builtin {
+        name: "max",
+        arity: 1..=usize::MAX,
+        returns: Type::Any,
+        call: max,
+    },
+    Builtin {
+        name: "round",
+        arity: 1..=2,
+        returns: Type::Number,
+        call: round,
+    },
+    Builtin {
+        name: "starts_with",
+        arity: 2..=2,
+        returns: Type::Bool,
+        call: starts_with,
+    },
+    Builtin {
+        name: "ends_with",
+        arity: 2..=2,
+        returns: Type::Bool,
+        call: ends_with,
+    },
+    Builtin {
+        name: "contains",
+        arity: 2..=2,
+        returns: Type::Bool,
+        call: contains,
+    },
+];
+
+pub fn builtin(name: &str) -> Option<&'static Builtin> {
+    BUILTINS.iter().find(|builtin| builtin.name == name)
+}
+
+// Describes how many arguments a function takes, e.g. "1 argument", "at least 1 argument"
+pub fn describe_arity(arity: &RangeInclusive<usize>) -> String {
+    let plural = |n: usize| if n == 1 { "argument" } else { "arguments" };
+    match (*arity.start(), *arity.end()) {
+        (min, max) if min == max => format!("{min} {}", plural(min)),
+        (min, usize::MAX) => format!("at least {min} {}", plural(min)),
+        (min, max) => format!("{min} to {max} arguments"),
+    }
+}
+
+fn invalid_argument(function: &str, value: &SimpleValue) -> EvalErrorKind {
+    EvalErrorKind::InvalidArgument {
+        function: function.to_string(),
+        value: value.to_string(),
+    }
+}
+
+fn string<'a>(function: &str, value: &'a SimpleValue) -> Result<&'a str, EvalErrorKind> {
+    match value {
+        SimpleValue::Str(s) => Ok(s),
+        _ => Err(invalid_argument(function, value)),
+    }
+}
+
+fn number(function: &str, value: &SimpleValue) -> Result<f64, EvalErrorKind> {
+    match value {
+        SimpleValue::Number(n) => Ok(*n),
+        _ => Err(invalid_argument(function, value)),
+    }
+}
+
+// The number of characters in a string or entries in a list or map
+fn len(args: &[SimpleValue]) -> Result<SimpleValue, EvalErrorKind> {
+    let len = match &args[0] {
+        SimpleValue::List(items) => items.len(),
+        SimpleValue::Map(map) => map.len(),
+        arg => string("len", arg)?.chars().count(),
+    };
+    Ok(SimpleValue::Number(len as f64))
+}
+
+fn lower(args: &[SimpleValue]) -> Result<SimpleValue, EvalErrorKind> {
+    Ok(SimpleValue::Str(string("lower", &args[0])?.to_lowercase()))
+}
+
+fn upper(args: &[SimpleValue]) -> Result<SimpleValue, EvalErrorKind> {
+    Ok(SimpleValue::Str(string("upper", &args[0])?.to_uppercase()))
+}
+
+fn trim(args: &[SimpleValue]) -> Result<SimpleValue, EvalErrorKind> {
+    Ok(SimpleValue::Str(
+        string("trim", &args[0])?.trim().to_string(),
+    ))
+}
+
+fn abs(args: &[SimpleValue]) -> Result<SimpleValue, EvalErrorKind> {
+    Ok(SimpleValue::Number(number("abs", &args[0])?.abs()))
+}
+
+// The least or greatest of several numbers or several strings
+fn extreme(
+    function: &str,
+    args: &[SimpleValue],
+    keep: fn(&SimpleValue, &SimpleValue) -> bool,
+) -> Result<SimpleValue, EvalErrorKind> {
+    let mut result = &args[0];
+    for arg in args {
+        match (result, arg) {
+            (SimpleValue::Number(_), SimpleValue::Number(_))
+            | (SimpleValue::Str(_), SimpleValue::Str(_)) => {
+                if keep(arg, result) {
+                    result = arg;
+                }
+            }
+            (SimpleValue::Number(_) | SimpleValue::Str(_), _) => {
+                return Err(invalid_argument(function, arg))
+            }
+            _ => return Err(invalid_argument(function, result)),
+        }
+    }
+    Ok(result.clone())
+}
+
+fn min(args: &[SimpleValue]) -> Result<SimpleValue, EvalErrorKind> {
+    extreme("min", args, |a, b| a < b)
+}
+
+fn max(args: &[SimpleValue]) -> Result<SimpleValue, EvalErrorKind> {
+    extreme("max", args, |a, b| a > b)
+}
+
+// Rounds half away from zero, optionally to a number of decimal places
+fn round(args: &[SimpleValue]) -> Result<SimpleValue, EvalErrorKind> {
+    let n = number("round", &args[0])?;
+    let digits = match args.get(1) {
+        Some(digits) => number("round", digits)?,
+        None => 0.0,
+    };
+    if digits.fract() != 0.0 {
+        return Err(invalid_argument("round", &args[1]));
+    }
+    // 10^digits overflows to infinity or underflows to zero past about 308
+    let scale = 10f64.powf(digits);
+    if !scale.is_finite() || scale == 0.0 {
+        return Err(invalid_argument("round", &args[1]));
+    }
+    let scaled = n * scale;
+    if !scaled.is_finite() {
+        // n is too large to have any digits at that scale, so it's already round
+        return Ok(SimpleValue::Number(n));
+    }
+    Ok(SimpleValue::Number(scaled.round() / scale))
+}
+
+pub fn starts_with(args: &[SimpleValue]) -> Result<SimpleValue, EvalErrorKind> {
+    let (s, prefix) = (
+        string("starts_with", &args[0])?,
+        string("starts_with", &args[1])?,
+    );
+    Ok(SimpleValue::Bool(s.starts_with(prefix)))
+}
+
+pub fn ends_with(args: &[SimpleValue]) -> Result<SimpleValue, EvalErrorKind> {
+    let (s, suffix) = (
+        string("ends_with", &args[0])?,
+        string("ends_with", &args[1])?,
+    );
+    Ok(SimpleValue::Bool(s.ends_with(suffix)))
+}
+
+// Whether a string contains a substring or a list contains a value
+pub fn contains(args: &[SimpleValue]) -> Result<SimpleValue, EvalErrorKind> {
+    if let SimpleValue::List(items) = &args[0] {
+        return Ok(SimpleValue::Bool(items.contains(&args[1])));
+    }
+    let (s, needle) = (string("contains", &args[0])?, string("contains", &args[1])?);
+    Ok(SimpleValue::Bool(s.contains(needle)))
+}
+
+#[test]
+fn test_builtins() {
+    let num = SimpleValue::Number;
+    let str = |s: &str| SimpleValue::Str(s.to_string());
+    let calls = [
+        ("len", vec![str("héllo")], num(5.0)),
+        (
+            "len",
+            vec![SimpleValue::List(vec![num(1.0), str("a")])],
+            num(2.0),
+        ),
+        ("lower", vec![str("A@Corp.COM")], str("a@corp.com")),
+        ("upper", vec![str("abc")], str("ABC")),
+        ("trim", vec![str("  a b \n")], str("a b")),
+        ("abs", vec![num(-2.5)], num(2.5)),
+        ("min", vec![num(3.0), num(-1.0), num(2.0)], num(-1.0)),
+        ("max", vec![num(3.0), num(-1.0), num(2.0)], num(3.0)),
+        ("min", vec![str("b"), str("a")], str("a")),
+        ("max", vec![num(7.0)], num(7.0)),
+        ("round", vec![num(2.5)], num(3.0)),
+        ("round", vec![num(-2.5)], num(-3.0)),
+        ("round", vec![num(1.2345), num(2.0)], num(1.23)),
+        ("round", vec![num(1234.5), num(-2.0)], num(1200.0)),
+        ("round", vec![num(1e300), num(300.0)], num(1e300)),
+        (
+            "starts_with",
+            vec![str("/api/v1"), str("/api")],
+            SimpleValue::Bool(true),
+        ),
+        (
+            "ends_with",
+            vec![str("a.csv"), str(".txt")],
+            SimpleValue::Bool(false),
+        ),
+        (
+            "contains",
+            vec![str("haystack"), str("st")],
+            SimpleValue::Bool(true),
+        ),
+        (
+            "contains",
+            vec![SimpleValue::List(vec![str("a"), num(1.0)]), num(1.0)],
+            SimpleValue::Bool(true),
+        ),
+        (
+            "contains",
+            vec![SimpleValue::List(vec![str("ab")]), str("a")],
+            SimpleValue::Bool(false),
+        ),
+    ];
+    for (name, args, expected) in calls.iter() {
+        let result = (builtin(name).unwrap().call)(args).unwrap();
+        assert_eq!(result, *expected, "{name}({args:?})");
+    }
+
+    let errors = [
+        ("len", vec![num(1.0)], "1"),
+        ("abs", vec![str("a")], "a"),
+        ("min", vec![num(1.0), str("a")], "a"),
+        ("max", vec![SimpleValue::None, num(1.0)], "none"),
+        ("round", vec![num(1.0), num(0.5)], "0.5"),
+        ("round", vec![num(1.5), num(400.0)], "400"),
+        ("round", vec![num(1.5), num(-400.0)], "-400"),
+        ("contains", vec![str("a"), SimpleValue::Bool(true)], "true"),
+        ("contains", vec![num(1.0), str("1")], "1"),
+    ];
+    for (name, args, value) in errors.iter() {
+        let err = (builtin(name).unwrap().call)(args).unwrap_err();
+        let expected = EvalErrorKind::InvalidArgument {
+            function: name.to_string(),
+            value: value.to_string(),
+        };
+        assert_eq!(err, expected, "{name}({args:?})");
+    }
+}
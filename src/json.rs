@@ -0,0 +1,86 @@
+use crate::{ContextProvider, Value};
+
+impl From<&serde_json::Value> for Value {
+    fn from(value: &serde_json::Value) -> Value {
+        match value {
+            serde_json::Value::Null => Value::None,
+            serde_json::Value::Bool(b) => Value::Bool(*b),
+            // Every JSON number fits in an f64, if not always exactly
+            serde_json::Value::Number(n) => Value::Number(n.as_f64().unwrap_or(f64::NAN)),
+            serde_json::Value::String(s) => Value::Str(s.clone()),
+            serde_json::Value::Array(items) => Value::List(items.iter().map(Value::from).collect()),
+            serde_json::Value::Object(map) => Value::Map(
+                map.iter()
+                    .map(|(k, v)| (k.clone(), Value::from(v)))
+                    .collect(),
+            ),
+        }
+    }
+}
+
+// Paths are resolved by walking down from the top-level fields of the
+// document, converting only the value that's reached. Other documents have
+// nothing a path could refer to.
+impl ContextProvider for serde_json::Value {
+    fn get(&self, path: &[&str]) -> Option<Value> {
+        if path.is_empty() {
+            return None;
+        }
+        let mut value = self;
+        for segment in path {
+            value = value.as_object()?.get(*segment)?;
+        }
+        Some(Value::from(value))
+    }
+}
+
+#[test]
+fn test_json() {
+    use crate::{new, CoolRuleError, EvalErrorKind};
+
+    let document = serde_json::json!({
+        "user": {
+            "email": "ada@corp.com",
+            "age": 36,
+            "roles": ["admin", "ops"],
+            "manager": null,
+        },
+        "order": {
+            "items": [
+                {"sku": "a1", "price": 5.5},
+                {"sku": "b2", "price": 150},
+            ],
+        },
+        "flag": true,
+    });
+
+    let exprs = [
+        ("user.email ends_with \"@corp.com\"", true),
+        ("user.age > 18 and user.age < 40", true),
+        ("user.roles ∩ (\"admin\")", true),
+        ("user.manager is none", true),
+        ("flag = true", true),
+        ("order.items[0].price = 5.5", true),
+        ("\"b2\" in order.items[*].sku", true),
+        ("any(item in order.items: item.price > 100)", true),
+        ("len(user.roles) = 3", false),
+    ];
+    for (expr, expected) in exprs.iter() {
+        let cr = new(expr).unwrap();
+        assert_eq!(cr.test_with_json(&document).unwrap(), *expected, "{expr}");
+    }
+
+    match new("user.name = 1").unwrap().test_with_json(&document) {
+        Err(CoolRuleError::EvalError(e)) => assert_eq!(
+            e.kind(),
+            &EvalErrorKind::MissingVariable {
+                path: vec!["user".to_string(), "name".to_string()]
+            }
+        ),
+        _ => panic!("expected an eval error"),
+    }
+    assert!(new("1 = 1")
+        .unwrap()
+        .test_with_json(&serde_json::json!([1]))
+        .unwrap());
+}
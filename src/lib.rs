@@ -5,7 +5,7 @@
 //! # Usage
 //!
 //! To use this library, you need to create a `CoolRule` instance by parsing a boolean expression string.
-//! The library supports evaluating boolean expressions containing various operations such as `and`, `or`, `not`, comparisons, and set membership checks.
+//! The library supports evaluating boolean expressions containing various operations such as `and`, `or`, `not` (also `!` and `¬`), comparisons, and set membership checks.
 //! Expressions can be evaluated with or without a context, where the context provides values for variables used in the expression.
 //!
 //! ## Examples
@@ -29,31 +29,228 @@
 //! let result = expr.test_with_context(&context).unwrap(); // true
 //! ```
 //!
+//! Comparisons can be made between arithmetic expressions using `+`, `-`, `*`,
+//! `/`, `%` and unary `-`, with the usual precedence:
+//!
+//! ```
+//! use coolrule::Value;
+//! use std::collections::HashMap;
+//!
+//! let expr = coolrule::new("order.total - order.discount > 100").unwrap();
+//! let context = HashMap::from([
+//!     (vec!["order", "total"], Value::Number(150.0)),
+//!     (vec!["order", "discount"], Value::Number(20.0)),
+//! ]);
+//! assert!(expr.test_with_context(&context).unwrap());
+//! ```
+//!
+//! Values can be passed through the built-in functions `len`, `lower`, `upper`,
+//! `trim`, `abs`, `min`, `max`, `round`, `starts_with`, `ends_with` and
+//! `contains`, which also tests whether a list holds a value. The last three
+//! can also be written as operators:
+//!
+//! ```
+//! use coolrule::Value;
+//! use std::collections::HashMap;
+//!
+//! let expr = coolrule::new("lower(user.email) ends_with \"@corp.com\"").unwrap();
+//! let context = HashMap::from([(vec!["user", "email"], Value::Str("Ada@Corp.COM".into()))]);
+//! assert!(expr.test_with_context(&context).unwrap());
+//! ```
+//!
+//! Context values can be lists, which work anywhere a group of values does:
+//!
+//! ```
+//! use coolrule::Value;
+//! use std::collections::HashMap;
+//!
+//! let expr = coolrule::new("user.roles ∩ (\"admin\", \"ops\")").unwrap();
+//! let roles = Value::List(vec![Value::Str("dev".into()), Value::Str("ops".into())]);
+//! let context = HashMap::from([(vec!["user", "roles"], roles)]);
+//! assert!(expr.test_with_context(&context).unwrap());
+//! ```
+//!
+//! Nested objects can be passed as [`Value::Map`]s and are walked along the
+//! property path, so `request.user.id` finds `id` in the `user` map of the
+//! `request` map when there's no `["request", "user", "id"]` key. Lists can be
+//! indexed with `items[0]`, or `items[-1]` to count from the end, and
+//! `items[*].sku` collects the `sku` of every item into a list.
+//!
+//! `any`, `all` and `none` test a condition against each item of a list or
+//! group, with the item bound to a variable:
+//!
+//! ```text
+//! any(item in order.items: item.price > 100)
+//! all(address in user.addresses: address.country in ("DE", "FR", "NL"))
+//! ```
+//!
+//! Applications can make their own functions callable by registering them in
+//! [`Functions`].
+//!
+//! Strings can be matched against regular expressions with `=~` (or `matches`)
+//! and `!~`, e.g. `user.email =~ "^[a-z]+@example\.com$"`. Patterns must be
+//! string literals and are compiled when the rule is parsed, so an invalid
+//! pattern is a parse error. Matching uses the [regex](https://docs.rs/regex)
+//! crate, which runs in linear time and is safe to use with untrusted rules.
+//!
+//! `like` and `ilike` match SQL-style patterns, where `%` matches any run of
+//! characters and `_` matches one. `glob` and `iglob` match shell-style patterns
+//! using `*`, `?` and classes like `[a-z]` or `[!0-9]`. In both, a backslash
+//! matches the next character literally and the `i` variants ignore case:
+//!
+//! ```
+//! let expr = coolrule::new(r#""/api/v1" like "/api/%" and "REPORT.CSV" iglob "*.csv""#).unwrap();
+//! assert!(expr.test().unwrap());
+//! ```
+//!
+//! ## Errors
+//!
+//! Expressions that can't be parsed produce a [`Diagnostic`] pointing at the
+//! problem, which renders the offending line with a caret:
+//!
+//! ```
+//! let Err(coolrule::CoolRuleError::ParseError(diagnostic)) = coolrule::new("x > ") else {
+//!     panic!("expected a parse error");
+//! };
+//! assert_eq!(diagnostic.message, "expected a value after `>`");
+//! println!("{diagnostic}");
+//! ```
+//!
+//! Errors raised while evaluating carry an [`EvalErrorKind`] to branch on and
+//! the [`Span`] of the comparison that failed:
+//!
+//! ```
+//! use coolrule::{CoolRuleError, EvalErrorKind};
+//!
+//! let expr = coolrule::new("1 = 1 and foo.bar > 3").unwrap();
+//! let Err(CoolRuleError::EvalError(e)) = expr.test() else {
+//!     panic!("expected an eval error");
+//! };
+//! assert!(matches!(e.kind(), EvalErrorKind::MissingVariable { path } if path == &["foo", "bar"]));
+//! assert_eq!((e.span().start, e.span().end), (10, 21));
+//! ```
+//!
+//! ## Precedence
+//!
+//! `not` binds tighter than `and`, which binds tighter than `or`, so
+//! `a or b and c` means `a or (b and c)`. Rules written for older versions of
+//! coolrule, which nested every chain to the right, can be parsed with
+//! [`Precedence::Boolrule`] via [`new_with_options`].
+//!
+//! ## Short-circuiting
+//!
+//! `and`/`or` stop evaluating once their result is known, so guard clauses like
+//! `x isnot none and x.y > 3` work when `x` is none. Quantifiers likewise stop
+//! at the first item that decides their result. Use [`Evaluation::Eager`] to
+//! evaluate every comparison and surface the first error instead.
+//!
 
+mod compiler;
+mod context;
+mod diagnostic;
+mod display;
 mod evaluator;
+mod explain;
+mod functions;
+#[cfg(feature = "serde")]
+mod json;
 mod parser;
+mod partial;
+mod provider;
+mod schema;
+mod variables;
+#[cfg(test)]
+mod walker;
 
-use evaluator::EvalError;
 use parser::{BooleanExpression, SimpleValue};
-use std::collections::HashMap;
+use std::{collections::HashMap, fmt};
+
+pub use context::RuleContext;
+#[cfg(feature = "derive")]
+pub use coolrule_derive::RuleContext;
+pub use diagnostic::Diagnostic;
+pub use evaluator::{Comparison, EvalError, EvalErrorKind, Evaluation, Truth};
+pub use explain::{Outcome, Step, Trace};
+pub use functions::{Functions, Type};
+pub use parser::{Precedence, Span};
+pub use partial::Partial;
+pub use provider::{AsyncContextProvider, ContextProvider};
+pub use schema::{CheckError, CheckErrorKind, Field, Schema};
+pub use variables::Variable;
 
 #[derive(Debug)]
 pub enum CoolRuleError {
     EvalError(EvalError),
-    ParseError(pom::Error),
+    ParseError(Diagnostic),
 }
 
 /// Represents possible values that can be used in boolean expressions.
+#[derive(Debug, Clone, PartialEq)]
 pub enum Value {
     Number(f64),
     Str(String),
     Bool(bool),
     None,
+    /// A list of values, which the set operators like `in` and `∩` treat the
+    /// same as a group of values written in the expression.
+    List(Vec<Value>),
+    /// A nested object whose entries can be reached with a property path,
+    /// e.g. `request.user.id` for a map under `["request"]`.
+    Map(HashMap<String, Value>),
+}
+
+impl From<&Value> for SimpleValue {
+    fn from(value: &Value) -> SimpleValue {
+        match value {
+            Value::Number(n) => SimpleValue::Number(*n),
+            Value::Str(s) => SimpleValue::Str(s.clone()),
+            Value::Bool(b) => SimpleValue::Bool(*b),
+            Value::None => SimpleValue::None,
+            Value::List(items) => SimpleValue::List(items.iter().map(SimpleValue::from).collect()),
+            Value::Map(map) => SimpleValue::Map(
+                map.iter()
+                    .map(|(k, v)| (k.clone(), SimpleValue::from(v)))
+                    .collect(),
+            ),
+        }
+    }
+}
+
+impl From<SimpleValue> for Value {
+    fn from(value: SimpleValue) -> Value {
+        match value {
+            SimpleValue::Number(n) => Value::Number(n),
+            SimpleValue::Str(s) => Value::Str(s),
+            SimpleValue::Bool(b) => Value::Bool(b),
+            SimpleValue::None | SimpleValue::PropertyPath(_) => Value::None,
+            SimpleValue::List(items) => Value::List(items.into_iter().map(Value::from).collect()),
+            SimpleValue::Map(map) => {
+                Value::Map(map.into_iter().map(|(k, v)| (k, Value::from(v))).collect())
+            }
+        }
+    }
+}
+
+/// Options that control how an expression is parsed and evaluated.
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    /// How chains of `and`/`or` are grouped. Defaults to [`Precedence::Standard`].
+    pub precedence: Precedence,
+    /// Whether `and`/`or` short-circuit. Defaults to [`Evaluation::ShortCircuit`].
+    pub evaluation: Evaluation,
+    /// Whether comparing values of different types is an error. Defaults to
+    /// [`Comparison::Loose`].
+    pub comparison: Comparison,
+    /// Functions callable from the expression in addition to the built-ins.
+    pub functions: Functions,
 }
 
 /// Represents a parsed and processed boolean expression.
 pub struct CoolRule {
     boolean_expression: BooleanExpression,
+    options: Options,
+    // The expression compiled for `test` and friends
+    program: Program,
 }
 
 /// Creates a new `CoolRule` instance by parsing the given boolean expression string.
@@ -66,22 +263,168 @@ pub struct CoolRule {
 ///
 /// A `Result` containing a `CoolRule` instance if parsing is successful, or a `CoolRuleError` if an error occurs during parsing.
 pub fn new(expr: &str) -> Result<CoolRule, CoolRuleError> {
-    match parse(expr) {
-        Ok(boolean_expression) => Ok(CoolRule {
-            boolean_expression: boolean_expression,
-        }),
+    new_with_options(expr, Options::default())
+}
+
+/// Creates a new `CoolRule` instance like [`new`] but with the given `Options`.
+///
+/// # Examples
+///
+/// ```
+/// use coolrule::{Options, Precedence};
+///
+/// // Group `and`/`or` chains the way older versions of coolrule did
+/// let options = Options {
+///     precedence: Precedence::Boolrule,
+///     ..Default::default()
+/// };
+/// let expr = coolrule::new_with_options("1 = 2 and 1 = 2 or 1 = 1", options).unwrap();
+/// assert!(!expr.test().unwrap());
+/// ```
+pub fn new_with_options(expr: &str, options: Options) -> Result<CoolRule, CoolRuleError> {
+    match parse(expr, &options) {
+        Ok(boolean_expression) => Ok(CoolRule::compiled(boolean_expression, options)),
         Err(e) => Err(CoolRuleError::ParseError(e)),
     }
 }
 
 impl CoolRule {
+    fn compiled(boolean_expression: BooleanExpression, options: Options) -> CoolRule {
+        CoolRule {
+            program: compile(&boolean_expression),
+            boolean_expression,
+            options,
+        }
+    }
+
+    /// Sets how `and`/`or` evaluate their operands for subsequent calls to
+    /// [`test`](CoolRule::test) and [`test_with_context`](CoolRule::test_with_context).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use coolrule::Evaluation;
+    ///
+    /// let mut expr = coolrule::new("1 = 1 or x > 3").unwrap();
+    /// assert!(expr.test().unwrap());
+    ///
+    /// // Surface the missing `x` even though it can't change the result
+    /// expr.set_evaluation(Evaluation::Eager);
+    /// assert!(expr.test().is_err());
+    /// ```
+    pub fn set_evaluation(&mut self, evaluation: Evaluation) {
+        self.options.evaluation = evaluation;
+    }
+
+    /// Sets how comparisons between values of different types behave for
+    /// subsequent evaluations.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use coolrule::Comparison;
+    ///
+    /// let mut expr = coolrule::new("7 = true").unwrap();
+    /// assert!(!expr.test().unwrap());
+    ///
+    /// expr.set_comparison(Comparison::Strict);
+    /// assert!(expr.test().is_err());
+    /// ```
+    pub fn set_comparison(&mut self, comparison: Comparison) {
+        self.options.comparison = comparison;
+    }
+
+    /// The property paths the expression reads from its context, in the order
+    /// they first appear. Paths under a quantifier's variable, like `item.price`
+    /// in `any(item in items: item.price > 100)`, aren't included.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let expr = coolrule::new("user.age >= 18 and user.country in (home, \"GB\")").unwrap();
+    /// let paths: Vec<_> = expr.variables().into_iter().map(|v| v.path.join(".")).collect();
+    /// assert_eq!(paths, ["user.age", "user.country", "home"]);
+    /// ```
+    pub fn variables(&self) -> Vec<Variable> {
+        variables::variables(&self.boolean_expression)
+    }
+
+    /// Checks the expression against the shape of the context it will be
+    /// evaluated with, reporting property paths that aren't in the schema,
+    /// comparisons between incompatible types, operators applied to values
+    /// that aren't iterable and literals that aren't allowed. See [`Schema`].
+    pub fn check(&self, schema: &Schema) -> Result<(), Vec<CheckError>> {
+        schema::check(&self.boolean_expression, schema)
+    }
+
+    /// Evaluates as much of the expression as `known` decides, e.g. the parts
+    /// about the tenant or region when the rest of the context is only known
+    /// per request. Conditions that only read known paths are folded into the
+    /// `and`/`or` around them, leaving either the result or a smaller rule to
+    /// evaluate later. Conditions that read unknown paths, or that fail, are
+    /// left in the residual rule, whose errors point into this expression.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use coolrule::{Partial, Value};
+    /// use std::collections::HashMap;
+    ///
+    /// let expr = coolrule::new("region = \"eu\" and (plan = \"pro\" or amount < 100)").unwrap();
+    ///
+    /// let known = HashMap::from([(vec!["region"], Value::Str("eu".into()))]);
+    /// let Partial::Residual(residual) = expr.partial_eval(&known) else {
+    ///     panic!("plan and amount aren't known yet");
+    /// };
+    /// assert_eq!(residual.to_string(), "plan = \"pro\" or amount < 100");
+    ///
+    /// let known = HashMap::from([(vec!["region"], Value::Str("us".into()))]);
+    /// assert!(matches!(expr.partial_eval(&known), Partial::Decided(false)));
+    /// ```
+    pub fn partial_eval<P: ContextProvider + ?Sized>(&self, known: &P) -> Partial {
+        let conditions = Conditions::new(&self.program, &known, &self.options);
+        partial::partial_eval(self, &conditions)
+    }
+
+    /// Evaluates the expression with the given context like
+    /// [`test_with_provider`](CoolRule::test_with_provider), recording how
+    /// each part was evaluated, e.g. to show why a rule didn't pass. See
+    /// [`Trace`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use coolrule::Value;
+    /// use std::collections::HashMap;
+    ///
+    /// let expr = coolrule::new("region = \"eu\" and (plan = \"pro\" or amount < 100)").unwrap();
+    /// let context = HashMap::from([
+    ///     (vec!["region"], Value::Str("us".into())),
+    ///     (vec!["plan"], Value::Str("pro".into())),
+    /// ]);
+    /// assert_eq!(
+    ///     expr.explain(&context).to_string(),
+    ///     "false: and\n  \
+    ///        false: region = \"eu\" (\"us\" = \"eu\")\n  \
+    ///        skipped: (plan = \"pro\" or amount < 100)\n"
+    /// );
+    /// ```
+    pub fn explain<P: ContextProvider + ?Sized>(&self, context: &P) -> Trace {
+        explain(
+            &self.boolean_expression,
+            &self.program,
+            &context,
+            &self.options,
+        )
+    }
+
     /// Evaluates the boolean expression without any context.
     ///
     /// # Returns
     ///
     /// A `Result` containing a boolean indicating the evaluation result if successful, or a `CoolRuleError` if an error occurs during evaluation.
     pub fn test(&self) -> Result<bool, CoolRuleError> {
-        match eval(&self.boolean_expression) {
+        match eval(&self.program, &self.options) {
             Ok(b) => Ok(b),
             Err(e) => Err(CoolRuleError::EvalError(e)),
         }
@@ -100,30 +443,113 @@ impl CoolRule {
         &self,
         context: &HashMap<Vec<&str>, Value>,
     ) -> Result<bool, CoolRuleError> {
-        let mut ctx: HashMap<Vec<&str>, SimpleValue> = HashMap::new();
-        context.iter().for_each(|(k, v)| {
-            ctx.insert(
-                k.to_vec(),
-                match v {
-                    Value::Number(n) => SimpleValue::Number(*n),
-                    Value::Str(s) => SimpleValue::Str(s.clone()),
-                    Value::Bool(b) => SimpleValue::Bool(*b),
-                    Value::None => SimpleValue::None,
-                },
-            );
-        });
-        match eval_with_context(&self.boolean_expression, &ctx) {
+        self.test_with_provider(context)
+    }
+
+    /// Evaluates the boolean expression, asking `provider` for the values of
+    /// property paths as they're reached. Each path is asked for at most once.
+    pub fn test_with_provider<P: ContextProvider + ?Sized>(
+        &self,
+        provider: &P,
+    ) -> Result<bool, CoolRuleError> {
+        match eval_with_context(&self.program, &provider, &self.options) {
             Ok(b) => Ok(b),
             Err(e) => Err(CoolRuleError::EvalError(e)),
         }
     }
+
+    /// Evaluates the boolean expression like
+    /// [`test_with_provider`](CoolRule::test_with_provider) but with
+    /// three-valued logic, so a property path missing from the context makes
+    /// the conditions that read it [`Truth::Unknown`] instead of failing the
+    /// evaluation. Other errors still fail it.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use coolrule::{Truth, Value};
+    /// use std::collections::HashMap;
+    ///
+    /// let context = HashMap::from([(vec!["age"], Value::Number(15.0))]);
+    ///
+    /// let expr = coolrule::new("age >= 18 and country = \"GB\"").unwrap();
+    /// assert_eq!(expr.test_with_unknowns(&context).unwrap(), Truth::False);
+    ///
+    /// let expr = coolrule::new("age < 18 and country = \"GB\"").unwrap();
+    /// assert_eq!(expr.test_with_unknowns(&context).unwrap(), Truth::Unknown);
+    /// ```
+    pub fn test_with_unknowns<P: ContextProvider + ?Sized>(
+        &self,
+        provider: &P,
+    ) -> Result<Truth, CoolRuleError> {
+        match eval_with_unknowns(&self.program, &provider, &self.options) {
+            Ok(truth) => Ok(truth),
+            Err(e) => Err(CoolRuleError::EvalError(e)),
+        }
+    }
+
+    /// Evaluates the boolean expression like
+    /// [`test_with_provider`](CoolRule::test_with_provider) but fetches the
+    /// values of property paths asynchronously. Each path is fetched at most
+    /// once and only if it's reached.
+    pub async fn test_async<P: AsyncContextProvider + Sync + ?Sized>(
+        &self,
+        provider: &P,
+    ) -> Result<bool, CoolRuleError> {
+        match eval_async(&self.program, provider, &self.options).await {
+            Ok(b) => Ok(b),
+            Err(e) => Err(CoolRuleError::EvalError(e)),
+        }
+    }
+
+    /// Evaluates the boolean expression against a Rust value, looking up
+    /// property paths with its [`RuleContext`] implementation, e.g. one
+    /// derived for a struct with `#[derive(RuleContext)]`.
+    pub fn test_with<T: RuleContext + ?Sized>(&self, context: &T) -> Result<bool, CoolRuleError> {
+        self.test_with_provider(&Derived(context))
+    }
+
+    /// Evaluates the boolean expression against a JSON document, looking up
+    /// property paths in its nested objects. `null` is `none`, arrays are lists
+    /// and objects are maps. Requires the `serde` feature.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let expr = coolrule::new("user.age >= 18 and \"admin\" in user.roles").unwrap();
+    /// let document = serde_json::json!({"user": {"age": 30, "roles": ["admin"]}});
+    /// assert!(expr.test_with_json(&document).unwrap());
+    /// ```
+    #[cfg(feature = "serde")]
+    pub fn test_with_json(&self, document: &serde_json::Value) -> Result<bool, CoolRuleError> {
+        self.test_with_provider(document)
+    }
+}
+
+// Writes the expression back out, normalising operators and whitespace
+impl fmt::Display for CoolRule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.boolean_expression)
+    }
 }
 
 use crate::{
-    evaluator::{eval, eval_with_context},
+    compiler::{compile, Program},
+    context::Derived,
+    evaluator::{eval, eval_async, eval_with_context, eval_with_unknowns, explain, Conditions},
     parser::parse,
 };
 
+// Like `test_with_context`, but also checks that the compiled rule evaluates
+// the same as walking the expression
+#[cfg(test)]
+impl CoolRule {
+    fn test_both(&self, context: &HashMap<Vec<&str>, Value>) -> Result<bool, CoolRuleError> {
+        walker::eval_both(&self.boolean_expression, &context, &self.options)
+            .map_err(CoolRuleError::EvalError)
+    }
+}
+
 #[test]
 fn test_bool_rule_test_suite() {
     let exprs = [
@@ -273,12 +699,454 @@ fn test_bool_rule_test_suite() {
         ("(1, 2) == (1, 2)", HashMap::new(), true),
         ("(4, none) >= (1, none)", HashMap::new(), true),
         ("none in (none)", HashMap::new(), true),
+        ("false == false", HashMap::new(), true),
+        ("false == true", HashMap::new(), false),
+        (
+            "truthy = nonempty",
+            HashMap::from([
+                (vec!["truthy"], Value::Bool(true)),
+                (vec!["nonempty"], Value::Bool(true)),
+            ]),
+            true,
+        ),
+        ("not 5 > 3", HashMap::new(), false),
+        ("not 5 < 3", HashMap::new(), true),
+        ("not (5 > 3 and 3 > 5)", HashMap::new(), true),
+        ("not (1=1 or 2=2) and (3 = 3)", HashMap::new(), false),
+        ("NOT (1 = 2)", HashMap::new(), true),
+        ("!(1 = 2) and 2 != 3", HashMap::new(), true),
+        ("¬(1, 2, 3) ⊆ (1, 2)", HashMap::new(), true),
+        ("not not 1 = 1", HashMap::new(), true),
+        (
+            "not x in (5, 6, 7)",
+            HashMap::from([(vec!["x"], Value::Number(8.0))]),
+            true,
+        ),
+        (
+            "nothing = 1 and not (nothing > 1)",
+            HashMap::from([(vec!["nothing"], Value::Number(1.0))]),
+            true,
+        ),
     ];
 
-    assert_eq!(new("1 == 1").unwrap().test().unwrap(), true);
+    assert!(new("1 == 1").unwrap().test_both(&HashMap::new()).unwrap());
     for (expr, ctx, result) in exprs.iter() {
         println!("{}", expr);
-        let cr = new(&expr).unwrap();
-        assert_eq!(cr.test_with_context(ctx).unwrap(), *result);
+        let cr = new(expr).unwrap();
+        assert_eq!(cr.test_both(ctx).unwrap(), *result);
+    }
+}
+
+#[test]
+fn test_precedence() {
+    let exprs = [
+        ("1 = 2 and 1 = 2 or 1 = 1", true, false),
+        ("1 = 1 or 1 = 2 and 1 = 2", true, true),
+        ("1 = 1 or 1 = 1 and 1 = 2", true, true),
+        ("(1 = 2 and 1 = 2) or 1 = 1", true, true),
+        ("1 = 2 and (1 = 2 or 1 = 1)", false, false),
+        ("not 1 = 1 and 1 = 2 or 1 = 1", true, false),
+        ("1 = 1 and 1 = 2 or 1 = 2 and 1 = 1 or 1 = 1", true, false),
+    ];
+    for (expr, standard, boolrule) in exprs.iter() {
+        let cr = new(expr).unwrap();
+        assert_eq!(cr.test_both(&HashMap::new()).unwrap(), *standard, "{expr}");
+        let cr = new_with_options(
+            expr,
+            Options {
+                precedence: Precedence::Boolrule,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(cr.test_both(&HashMap::new()).unwrap(), *boolrule, "{expr}");
+    }
+}
+
+#[test]
+fn test_short_circuit() {
+    let exprs = [
+        (
+            "x isnot none and x.y > 3",
+            HashMap::from([(vec!["x"], Value::None)]),
+            Some(false),
+            None,
+        ),
+        (
+            "x is none or x.y > 3",
+            HashMap::from([(vec!["x"], Value::None)]),
+            Some(true),
+            None,
+        ),
+        (
+            "x isnot none and x.y > 3",
+            HashMap::from([
+                (vec!["x"], Value::Number(1.0)),
+                (vec!["x", "y"], Value::Number(4.0)),
+            ]),
+            Some(true),
+            Some(true),
+        ),
+        (
+            "1 = 2 and (a > 1 or b > 1)",
+            HashMap::new(),
+            Some(false),
+            None,
+        ),
+        ("not (1 = 1 or a > 1)", HashMap::new(), Some(false), None),
+        ("1 = 2 and a > 1 or 1 = 1", HashMap::new(), Some(true), None),
+        // Errors on the left are always surfaced
+        ("a > 1 or 1 = 1", HashMap::new(), None, None),
+        ("a > 1 and 1 = 2", HashMap::new(), None, None),
+    ];
+    for (expr, ctx, short_circuit, eager) in exprs.iter() {
+        let mut cr = new(expr).unwrap();
+        assert_eq!(cr.test_both(ctx).ok(), *short_circuit, "{expr}");
+        cr.set_evaluation(Evaluation::Eager);
+        assert_eq!(cr.test_both(ctx).ok(), *eager, "{expr}");
+    }
+
+    // The first error in source order wins when evaluating eagerly
+    let mut cr = new("1 = 1 or a > 1 or b > 1").unwrap();
+    cr.set_evaluation(Evaluation::Eager);
+    match cr.test_both(&HashMap::new()) {
+        Err(CoolRuleError::EvalError(e)) => {
+            assert_eq!(e.to_string(), "a missing from context");
+            assert_eq!(
+                e.kind(),
+                &EvalErrorKind::MissingVariable {
+                    path: vec!["a".to_string()]
+                }
+            );
+            assert_eq!(e.span(), Span { start: 9, end: 14 });
+        }
+        _ => panic!("expected an eval error"),
+    }
+}
+
+#[test]
+fn test_functions() {
+    let mut functions = Functions::new();
+    functions.register("is_holiday", &[Type::Str], Type::Bool, |args| {
+        Ok(Value::Bool(args[0] == Value::Str("12-25".to_string())))
+    });
+    functions.register(
+        "risk_score",
+        &[Type::Str, Type::Number],
+        Type::Number,
+        |args| match &args[0] {
+            Value::Str(account) if account.is_empty() => Err("no account".to_string()),
+            _ => Ok(args[1].clone()),
+        },
+    );
+    // Breaks its declared return type
+    functions.register("rate", &[Type::Str], Type::Number, |args| {
+        Ok(args[0].clone())
+    });
+    // Shadows the built-in
+    functions.register("len", &[Type::Any], Type::Number, |_| {
+        Ok(Value::Number(42.0))
+    });
+    let options = Options {
+        functions,
+        ..Default::default()
+    };
+
+    let exprs = [
+        ("is_holiday(date) = true", true),
+        ("is_holiday(lower(\"12-25\")) = true", true),
+        ("risk_score(account, 0.5 + 0.25) * 4 = 3", true),
+        ("len(1) = 42", true),
+        ("upper(account) = \"ACME\"", true),
+    ];
+    let context = HashMap::from([
+        (vec!["date"], Value::Str("12-25".to_string())),
+        (vec!["account"], Value::Str("acme".to_string())),
+        (vec!["empty"], Value::Str("".to_string())),
+        (vec!["n"], Value::Number(1.0)),
+    ]);
+    for (expr, expected) in exprs.iter() {
+        let cr = new_with_options(expr, options.clone()).unwrap();
+        assert_eq!(cr.test_both(&context).unwrap(), *expected, "{expr}");
+    }
+
+    let parse_errors = [
+        ("is_workday(date) = true", "unknown function `is_workday`"),
+        (
+            "is_holiday(date, 1) = true",
+            "`is_holiday` takes 1 argument but 2 were given",
+        ),
+        (
+            "is_holiday(1) = true",
+            "`is_holiday` expects a string for argument 1 but got a number",
+        ),
+        (
+            "risk_score(account, is_holiday(date)) > 1",
+            "`risk_score` expects a number for argument 2 but got a bool",
+        ),
+    ];
+    for (expr, message) in parse_errors.iter() {
+        match new_with_options(expr, options.clone()) {
+            Err(CoolRuleError::ParseError(e)) => assert_eq!(e.message, *message, "{expr}"),
+            _ => panic!("{expr} should fail to parse"),
+        }
+    }
+
+    let eval_errors = [
+        (
+            "is_holiday(n) = true",
+            EvalErrorKind::InvalidArgument {
+                function: "is_holiday".to_string(),
+                value: "1".to_string(),
+            },
+        ),
+        (
+            "risk_score(empty, 1) > 1",
+            EvalErrorKind::FunctionFailed {
+                function: "risk_score".to_string(),
+                message: "no account".to_string(),
+            },
+        ),
+        (
+            "rate(account) > 1",
+            EvalErrorKind::FunctionFailed {
+                function: "rate".to_string(),
+                message: "returned a string instead of a number".to_string(),
+            },
+        ),
+    ];
+    for (expr, kind) in eval_errors.iter() {
+        let cr = new_with_options(expr, options.clone()).unwrap();
+        match cr.test_both(&context) {
+            Err(CoolRuleError::EvalError(e)) => assert_eq!(e.kind(), kind, "{expr}"),
+            _ => panic!("{expr} should fail to evaluate"),
+        }
+    }
+
+    // Registered functions are only visible to rules parsed with them
+    assert!(new("is_holiday(date) = true").is_err());
+}
+
+#[test]
+fn test_nested_context() {
+    let request = Value::Map(HashMap::from([
+        (
+            "user".to_string(),
+            Value::Map(HashMap::from([
+                ("id".to_string(), Value::Number(7.0)),
+                (
+                    "roles".to_string(),
+                    Value::List(vec![Value::Str("admin".to_string())]),
+                ),
+            ])),
+        ),
+        ("path".to_string(), Value::Str("/api".to_string())),
+    ]));
+    let context = HashMap::from([
+        (vec!["request"], request),
+        // Flat keys still work alongside nested ones
+        (vec!["request", "method"], Value::Str("GET".to_string())),
+        (vec!["limit"], Value::Number(10.0)),
+    ]);
+
+    let exprs = [
+        ("request.user.id = 7", true),
+        ("request.user.roles ∩ (\"admin\", \"ops\")", true),
+        ("request.path = \"/api\" and request.method = \"GET\"", true),
+        ("len(request.user) = 2", true),
+        ("request.user.id < limit", true),
+    ];
+    for (expr, expected) in exprs.iter() {
+        let cr = new(expr).unwrap();
+        assert_eq!(cr.test_both(&context).unwrap(), *expected, "{expr}");
+    }
+
+    let missing = [
+        ("request.user.name = 1", vec!["request", "user", "name"]),
+        ("request.path.len = 1", vec!["request", "path", "len"]),
+        ("limit.max = 1", vec!["limit", "max"]),
+    ];
+    for (expr, path) in missing.iter() {
+        match new(expr).unwrap().test_both(&context) {
+            Err(CoolRuleError::EvalError(e)) => assert_eq!(
+                e.kind(),
+                &EvalErrorKind::MissingVariable {
+                    path: path.iter().map(|s| s.to_string()).collect()
+                },
+                "{expr}"
+            ),
+            _ => panic!("{expr} should fail to evaluate"),
+        }
+    }
+}
+
+#[test]
+fn test_indexing() {
+    let item = |sku: &str, price: f64| {
+        Value::Map(HashMap::from([
+            ("sku".to_string(), Value::Str(sku.to_string())),
+            ("price".to_string(), Value::Number(price)),
+        ]))
+    };
+    let context = HashMap::from([
+        (
+            vec!["order", "items"],
+            Value::List(vec![item("a1", 5.0), item("b2", 150.0), item("c3", 20.0)]),
+        ),
+        (
+            vec!["matrix"],
+            Value::List(vec![
+                Value::List(vec![Value::Number(1.0), Value::Number(2.0)]),
+                Value::List(vec![Value::Number(3.0)]),
+            ]),
+        ),
+    ]);
+
+    let exprs = [
+        ("order.items[0].price = 5", true),
+        ("order.items[-1].sku = \"c3\"", true),
+        ("order.items[-3].sku = \"a1\"", true),
+        ("\"b2\" in order.items[*].sku", true),
+        (
+            "order.items[*].sku ⊆ (\"a1\", \"b2\", \"c3\", \"d4\")",
+            true,
+        ),
+        ("order.items[*].sku ∩ (\"x\", \"y\")", false),
+        ("max(order.items[1].price, 1) > 100", true),
+        ("len(order.items[*]) = 3", true),
+        ("matrix[1][0] = 3", true),
+        ("matrix[*][*] = (1, 2, 3)", true),
+        ("matrix[*][0] = (1, 3)", true),
+    ];
+    for (expr, expected) in exprs.iter() {
+        let cr = new(expr).unwrap();
+        assert_eq!(cr.test_both(&context).unwrap(), *expected, "{expr}");
+    }
+
+    let errors = [
+        (
+            "order.items[3].price = 1",
+            EvalErrorKind::IndexOutOfRange {
+                path: vec!["order".to_string(), "items".to_string()],
+                index: 3,
+                len: 3,
+            },
+        ),
+        (
+            "order.items[-4].price = 1",
+            EvalErrorKind::IndexOutOfRange {
+                path: vec!["order".to_string(), "items".to_string()],
+                index: -4,
+                len: 3,
+            },
+        ),
+        (
+            "matrix[*][1] = 1",
+            EvalErrorKind::IndexOutOfRange {
+                path: vec!["matrix".to_string(), "*".to_string()],
+                index: 1,
+                len: 1,
+            },
+        ),
+        (
+            "order.items[0].name = 1",
+            EvalErrorKind::MissingVariable {
+                path: vec![
+                    "order".to_string(),
+                    "items".to_string(),
+                    "0".to_string(),
+                    "name".to_string(),
+                ],
+            },
+        ),
+        (
+            "order.items[0].price[0] = 1",
+            EvalErrorKind::NotIterable {
+                value: "5".to_string(),
+            },
+        ),
+    ];
+    for (expr, kind) in errors.iter() {
+        match new(expr).unwrap().test_both(&context) {
+            Err(CoolRuleError::EvalError(e)) => assert_eq!(e.kind(), kind, "{expr}"),
+            _ => panic!("{expr} should fail to evaluate"),
+        }
+    }
+}
+
+#[test]
+fn test_quantifiers() {
+    let item = |price: f64, tags: &[&str]| {
+        Value::Map(HashMap::from([
+            ("price".to_string(), Value::Number(price)),
+            (
+                "tags".to_string(),
+                Value::List(tags.iter().map(|t| Value::Str(t.to_string())).collect()),
+            ),
+        ]))
+    };
+    let context = HashMap::from([
+        (
+            vec!["order", "items"],
+            Value::List(vec![item(50.0, &["eu"]), item(150.0, &["eu", "sale"])]),
+        ),
+        (vec!["empty"], Value::List(vec![])),
+        (vec!["item"], Value::Number(1.0)),
+        (vec!["limit"], Value::Number(100.0)),
+    ]);
+
+    let exprs = [
+        ("any(item in order.items: item.price > 100)", true),
+        ("all(item in order.items: item.price > 100)", false),
+        ("none(item in order.items: item.price > 200)", true),
+        ("all(item in order.items: \"eu\" in item.tags)", true),
+        (
+            "any(i in order.items: any(t in i.tags: t = \"sale\"))",
+            true,
+        ),
+        (
+            "all(i in order.items: any(t in i.tags: t = \"sale\"))",
+            false,
+        ),
+        // The rest of the context is still visible
+        ("any(i in order.items: i.price > limit)", true),
+        // The loop variable shadows the context
+        ("all(item in order.items: item isnot 1) and item = 1", true),
+        ("any(x in empty: x = 1)", false),
+        ("all(x in empty: x = 1)", true),
+        ("none(x in empty: x = 1)", true),
+        ("not any(x in (1, 2): x > 5)", true),
+        ("any(n in order.items[*].price: n * 2 = 100)", true),
+    ];
+    for (expr, expected) in exprs.iter() {
+        let cr = new(expr).unwrap();
+        assert_eq!(cr.test_both(&context).unwrap(), *expected, "{expr}");
+    }
+
+    // Stops at the first item that decides the result
+    let mut cr = new("any(i in order.items: i.price < 100 or i.missing > 1)").unwrap();
+    assert!(cr.test_both(&context).unwrap());
+    cr.set_evaluation(Evaluation::Eager);
+    match cr.test_both(&context) {
+        Err(CoolRuleError::EvalError(e)) => {
+            assert_eq!(
+                e.kind(),
+                &EvalErrorKind::MissingVariable {
+                    path: vec!["i".to_string(), "missing".to_string()]
+                }
+            );
+            assert_eq!(e.span(), Span { start: 39, end: 52 });
+        }
+        _ => panic!("expected an eval error"),
+    }
+
+    match new("any(x in limit: x > 1)").unwrap().test_both(&context) {
+        Err(CoolRuleError::EvalError(e)) => assert_eq!(
+            e.kind(),
+            &EvalErrorKind::NotIterable {
+                value: "100".to_string()
+            }
+        ),
+        _ => panic!("expected an eval error"),
     }
 }
@@ -1,7 +1,15 @@
+use crate::{
+    diagnostic::{describe_token, Diagnostic},
+    functions::{describe_arity, Function, Type},
+    Options,
+};
 use pom::parser::*;
+use regex::Regex;
+use std::collections::BTreeMap;
+use std::iter::Peekable;
 use std::str::{self, FromStr};
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub enum BinOp {
     Equal,              // =, ==, eq
     NotEqual,           // !=, ne, ≠
@@ -17,6 +25,33 @@ pub enum BinOp {
     SuperSetOf,         // ⊇
     IntersectionOf,     // ∩
     NotIntersectionOf,  // not∩
+    StartsWith,         // starts_with
+    EndsWith,           // ends_with
+    Contains,           // contains
+    Like,               // like
+    ILike,              // ilike
+    Glob,               // glob
+    IGlob,              // iglob
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum MatchOp {
+    Matches,    // matches, =~
+    NotMatches, // !~
+}
+
+/// A byte range in the source expression.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+/// An AST node along with the part of the source expression it was parsed from.
+#[derive(Debug, Clone, Copy)]
+pub struct Spanned<T> {
+    pub node: T,
+    pub span: Span,
 }
 
 #[derive(Debug, Clone)]
@@ -25,42 +60,250 @@ pub enum SimpleValue {
     Str(String),
     Bool(bool),
     None,
+    List(Vec<SimpleValue>),
+    Map(BTreeMap<String, SimpleValue>),
     // The path to a context value
     // e.g. `foo.bar` -> [`foo`, `bar`]
     PropertyPath(Vec<String>),
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
+pub enum ArithOp {
+    Add, // +
+    Sub, // -
+    Mul, // *
+    Div, // /
+    Rem, // %
+}
+
+// A step into a context value after its property path
+#[derive(Debug, Clone)]
+pub enum Accessor {
+    // `[0]`, or `[-1]` counting from the end
+    Index(i64),
+    // `[*]`, the following accessors apply to each item
+    Wildcard,
+    // `.price` after an index
+    Field(String),
+}
+
+// An operand of a comparison
+#[derive(Debug, Clone)]
+pub enum ValueExpression {
+    Value(SimpleValue),
+    // e.g. `items[0].price` -> [`items`], [Index(0), Field(`price`)]
+    Access(Vec<String>, Vec<Accessor>),
+    Negate(Box<Spanned<ValueExpression>>),
+    Call(Function, Vec<Spanned<ValueExpression>>),
+    Arithmetic(
+        Box<Spanned<ValueExpression>>,
+        ArithOp,
+        Box<Spanned<ValueExpression>>,
+    ),
+}
+
+#[derive(Debug, Clone)]
 pub enum PropertyVal {
-    SimpleValue(SimpleValue),
-    Group(Vec<SimpleValue>),
+    Value(Spanned<ValueExpression>),
+    Group(Vec<Spanned<ValueExpression>>),
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
+pub enum Quantifier {
+    Any,  // any
+    All,  // all
+    None, // none
+}
+
+#[derive(Debug, Clone)]
 pub enum BooleanCondition {
-    Comparison(PropertyVal, BinOp, PropertyVal),
+    Comparison(Spanned<PropertyVal>, Spanned<BinOp>, Spanned<PropertyVal>),
+    // The pattern is compiled when the expression is parsed
+    Match(Spanned<PropertyVal>, Spanned<MatchOp>, Spanned<Regex>),
+    // e.g. `any(item in order.items: item.price > 100)`
+    Quantified(
+        Quantifier,
+        Spanned<String>,
+        Spanned<PropertyVal>,
+        Box<BooleanExpression>,
+    ),
     Group(Box<BooleanExpression>),
+    Not(Box<Spanned<BooleanCondition>>),
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum AndOr {
     And,
     Or,
 }
 
-#[derive(Debug)]
-pub struct BooleanExpression {
-    pub initial: BooleanCondition,
-    pub conditions: Vec<(AndOr, BooleanCondition)>,
+impl AndOr {
+    // Higher binds tighter
+    fn binding_power(&self) -> u8 {
+        match self {
+            AndOr::Or => 1,
+            AndOr::And => 2,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum BooleanExpression {
+    Condition(Spanned<BooleanCondition>),
+    Binary(Box<BooleanExpression>, AndOr, Box<BooleanExpression>),
+}
+
+/// Controls how chains of `and`/`or` are grouped when there are no parentheses.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Precedence {
+    /// `and` binds tighter than `or` and both are left-associative,
+    /// so `a or b and c` means `a or (b and c)`.
+    #[default]
+    Standard,
+    /// Every chain is nested to the right regardless of the operators,
+    /// so `a and b or c` means `a and (b or c)`. This is how coolrule
+    /// evaluated rules before it understood operator precedence.
+    Boolrule,
+}
+
+// What the parser accepts at each point where it can fail, shown to rule authors
+const EXPECTED_VALUE: &[&str] = &[
+    "a number",
+    "a string",
+    "`true`",
+    "`false`",
+    "`none`",
+    "a property path",
+    "`(`",
+];
+const EXPECTED_OPERATOR: &[&str] = &[
+    "`+`",
+    "`-`",
+    "`*`",
+    "`/`",
+    "`%`",
+    "`=`",
+    "`!=`",
+    "`>`",
+    "`>=`",
+    "`<`",
+    "`<=`",
+    "`in`",
+    "`notin`",
+    "`is`",
+    "`isnot`",
+    "`⊆`",
+    "`⊇`",
+    "`∩`",
+    "`not∩`",
+    "`starts_with`",
+    "`ends_with`",
+    "`contains`",
+    "`like`",
+    "`ilike`",
+    "`glob`",
+    "`iglob`",
+    "`=~`",
+    "`!~`",
+    "`matches`",
+];
+const EXPECTED_PATTERN: &[&str] = &["a string"];
+const EXPECTED_INDEX: &[&str] = &["an integer", "`*`"];
+const EXPECTED_CLOSE_INDEX: &[&str] = &["`]`"];
+const EXPECTED_CONDITION: &[&str] = &["a comparison", "`not`", "`(`"];
+const EXPECTED_CLOSE_GROUP: &[&str] = &["`and`", "`or`", "`)`"];
+const EXPECTED_COLON: &[&str] = &["`:`"];
+const EXPECTED_CLOSE_VALUES: &[&str] = &["`,`", "`)`"];
+const EXPECTED_END: &[&str] = &["`and`", "`or`", "end of input"];
+const EXPECTED_QUOTE: &[&str] = &["`\"`"];
+
+// Once `parser` has been reached the input can't be anything else, so a failure
+// is reported with `message` rather than backtracking into other alternatives.
+// pom's ordered choice stops at `Expect` errors, which carry the message and, in
+// a `Custom` inner error, the newline separated list of expected tokens.
+fn cut<'a, O: 'a, M>(
+    parser: Parser<'a, u8, O>,
+    expected: &'static [&'static str],
+    message: M,
+) -> Parser<'a, u8, O>
+where
+    M: Fn(&'a [u8], usize) -> String + 'a,
+{
+    Parser::new(move |input: &'a [u8], start: usize| {
+        (parser.method)(input, start).map_err(|err| match err {
+            pom::Error::Expect { .. } => err,
+            _ => pom::Error::Expect {
+                message: message(input, start),
+                position: start,
+                inner: Box::new(pom::Error::Custom {
+                    message: expected.join("\n"),
+                    position: start,
+                    inner: Some(Box::new(err)),
+                }),
+            },
+        })
+    })
+}
+
+// Like `repeat(0..)` but doesn't swallow `cut` errors
+fn many<'a, O: 'a>(parser: Parser<'a, u8, O>) -> Parser<'a, u8, Vec<O>> {
+    Parser::new(move |input: &'a [u8], start: usize| {
+        let mut items = vec![];
+        let mut pos = start;
+        loop {
+            match (parser.method)(input, pos) {
+                Ok((item, next)) => {
+                    items.push(item);
+                    pos = next;
+                }
+                Err(err @ pom::Error::Expect { .. }) => return Err(err),
+                Err(_) => return Ok((items, pos)),
+            }
+        }
+    })
+}
+
+// Fails at `position` with `message`, stopping ordered choice like `cut`
+fn reject<'a, O: 'a>(position: usize, message: String) -> Parser<'a, u8, O> {
+    Parser::new(move |_, _| {
+        Err(pom::Error::Expect {
+            message: message.clone(),
+            position,
+            inner: Box::new(pom::Error::Custom {
+                message: String::new(),
+                position,
+                inner: None,
+            }),
+        })
+    })
+}
+
+fn spanned<'a, O: 'a>(parser: Parser<'a, u8, O>) -> Parser<'a, u8, Spanned<O>> {
+    (empty().pos() + parser + empty().pos()).map(|((start, node), end)| Spanned {
+        node,
+        span: Span { start, end },
+    })
+}
+
+fn text(input: &[u8], span: Span) -> String {
+    String::from_utf8_lossy(&input[span.start..span.end]).into_owned()
 }
 
 fn space<'a>() -> Parser<'a, u8, ()> {
     one_of(b" \t\r\n").repeat(0..).discard()
 }
 
+fn ident_char<'a>() -> Parser<'a, u8, u8> {
+    one_of(b"_abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789")
+}
+
 fn property_path<'a>() -> Parser<'a, u8, Vec<Vec<u8>>> {
-    let ascii = one_of(b"_abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789");
-    list(ascii.repeat(1..), sym(b'.'))
+    (ident_char().repeat(1..) + (sym(b'.') * ident_char().repeat(1..)).repeat(0..)).map(
+        |(first, mut rest)| {
+            rest.insert(0, first);
+            rest
+        },
+    )
 }
 
 fn lparen<'a>() -> Parser<'a, u8, ()> {
@@ -86,29 +329,47 @@ fn binary_op<'a>() -> Parser<'a, u8, BinOp> {
         | seq("⊇".as_bytes()).map(|_| BinOp::SuperSetOf)
         | seq("∩".as_bytes()).map(|_| BinOp::IntersectionOf)
         | seq("not∩".as_bytes()).map(|_| BinOp::NotIntersectionOf)
+        | seq(b"starts_with").map(|_| BinOp::StartsWith)
+        | seq(b"ends_with").map(|_| BinOp::EndsWith)
+        | seq(b"contains").map(|_| BinOp::Contains)
+        | seq(b"like").map(|_| BinOp::Like)
+        | seq(b"ilike").map(|_| BinOp::ILike)
+        | seq(b"glob").map(|_| BinOp::Glob)
+        | seq(b"iglob").map(|_| BinOp::IGlob)
+}
+
+fn match_op<'a>() -> Parser<'a, u8, MatchOp> {
+    (seq(b"=~") | seq(b"matches")).map(|_| MatchOp::Matches)
+        | seq(b"!~").map(|_| MatchOp::NotMatches)
 }
 
 fn real_number<'a>() -> Parser<'a, u8, f64> {
-    let integer = one_of(b"123456789") - one_of(b"0123456789").repeat(0..) | sym(b'0');
+    let integer = (one_of(b"123456789") - one_of(b"0123456789").repeat(0..)) | sym(b'0');
     let frac = sym(b'.') + one_of(b"0123456789").repeat(1..);
     let exp = one_of(b"eE") + one_of(b"+-").opt() + one_of(b"0123456789").repeat(1..);
     let number = sym(b'-').opt() + integer + frac.opt() + exp.opt();
     number
         .collect()
         .convert(str::from_utf8)
-        .convert(|s| f64::from_str(&s))
+        .convert(f64::from_str)
 }
 
 fn integer<'a>() -> Parser<'a, u8, u8> {
-    one_of(b"123456789") - one_of(b"0123456789").repeat(0..) | sym(b'0')
+    (one_of(b"123456789") - one_of(b"0123456789").repeat(0..)) | sym(b'0')
 }
 
 fn str<'a>() -> Parser<'a, u8, String> {
-    (sym(b'"') * none_of(b"\"").repeat(0..) - sym(b'"')).convert(String::from_utf8)
+    -sym(b'"')
+        * cut(
+            sym(b'"') * none_of(b"\"").repeat(0..) - sym(b'"'),
+            EXPECTED_QUOTE,
+            |_, _| "unterminated string".to_string(),
+        )
+        .convert(String::from_utf8)
 }
 
 fn bool<'a>() -> Parser<'a, u8, SimpleValue> {
-    ((seq(b"t") | seq(b"T"))
+    (((seq(b"t") | seq(b"T"))
         + (seq(b"r") | seq(b"R"))
         + (seq(b"u") | seq(b"U"))
         + (seq(b"e") | seq(b"E")))
@@ -118,81 +379,525 @@ fn bool<'a>() -> Parser<'a, u8, SimpleValue> {
             + (seq(b"l") | seq(b"L"))
             + (seq(b"s") | seq(b"S"))
             + (seq(b"e") | seq(b"E")))
-        .map(|_| SimpleValue::Bool(true))
+        .map(|_| SimpleValue::Bool(false)))
+        - !ident_char()
 }
 
 fn none<'a>() -> Parser<'a, u8, u8> {
     ((seq(b"n") | seq(b"N"))
         + (seq(b"o") | seq(b"O"))
         + (seq(b"n") | seq(b"N"))
-        + (seq(b"e") | seq(b"E")))
+        + (seq(b"e") | seq(b"E"))
+        - !ident_char())
     .map(|_| 0)
 }
 
-fn simple_value<'a>() -> Parser<'a, u8, SimpleValue> {
-    space()
-        * (real_number().map(|f| SimpleValue::Number(f))
+fn simple_value<'a>() -> Parser<'a, u8, Spanned<SimpleValue>> {
+    spanned(
+        real_number().map(SimpleValue::Number)
             | integer().map(|i| SimpleValue::Number(i.into()))
-            | str().map(|s| SimpleValue::Str(s))
+            | str().map(SimpleValue::Str)
             | bool()
             | none().map(|_| SimpleValue::None)
-            | property_path().map(|p| {
+            // `and`/`or` are reserved so that `x = and` reports the missing value
+            | (!and_or() * property_path()).map(|p| {
                 SimpleValue::PropertyPath(
                     p.iter()
                         .map(|byte_vec| String::from_utf8_lossy(byte_vec).into_owned())
                         .collect(),
                 )
-            }))
-        - space()
+            }),
+    )
 }
 
-fn property_val<'a>() -> Parser<'a, u8, PropertyVal> {
-    space()
-        * ((lparen() * list(simple_value(), sym(b',') * space()) - rparen())
-            .map(|g| PropertyVal::Group(g))
-            | simple_value().map(|s| PropertyVal::SimpleValue(s)))
-        - space()
+fn index<'a>() -> Parser<'a, u8, Accessor> {
+    let index = sym(b'*').map(|_| Accessor::Wildcard)
+        | (sym(b'-').opt() + integer())
+            .collect()
+            .convert(str::from_utf8)
+            .convert(i64::from_str)
+            .map(Accessor::Index);
+    let index = cut(index, EXPECTED_INDEX, |_, _| {
+        "expected an index after `[`".to_string()
+    });
+    let close = cut(sym(b']'), EXPECTED_CLOSE_INDEX, |_, _| {
+        "expected `]` to close the index".to_string()
+    });
+    sym(b'[') * space() * index - space() - close
+}
+
+fn field<'a>() -> Parser<'a, u8, Accessor> {
+    sym(b'.')
+        * ident_char()
+            .repeat(1..)
+            .convert(String::from_utf8)
+            .map(Accessor::Field)
+}
+
+// `items[0].price`, `items[-1]` or `items[*].sku`
+fn path_access<'a>() -> Parser<'a, u8, Spanned<ValueExpression>> {
+    let path = simple_value().convert(|v| match v.node {
+        SimpleValue::PropertyPath(p) => Ok(p),
+        _ => Err("not a property path"),
+    });
+    spanned(path + index() + many(index() | field())).map(|access| {
+        let ((path, first), rest) = access.node;
+        Spanned {
+            node: ValueExpression::Access(path, [vec![first], rest].concat()),
+            span: access.span,
+        }
+    })
+}
+
+fn additive_op<'a>() -> Parser<'a, u8, ArithOp> {
+    sym(b'+').map(|_| ArithOp::Add) | sym(b'-').map(|_| ArithOp::Sub)
+}
+
+fn multiplicative_op<'a>() -> Parser<'a, u8, ArithOp> {
+    sym(b'*').map(|_| ArithOp::Mul)
+        | sym(b'/').map(|_| ArithOp::Div)
+        | sym(b'%').map(|_| ArithOp::Rem)
+}
+
+// The type a value expression will evaluate to, if it's known before evaluation
+fn static_type(value_expression: &ValueExpression) -> Option<Type> {
+    match value_expression {
+        ValueExpression::Value(SimpleValue::PropertyPath(_)) | ValueExpression::Access(..) => None,
+        ValueExpression::Value(SimpleValue::Number(_)) => Some(Type::Number),
+        ValueExpression::Value(SimpleValue::Str(_)) => Some(Type::Str),
+        ValueExpression::Value(SimpleValue::Bool(_)) => Some(Type::Bool),
+        ValueExpression::Value(SimpleValue::None) => Some(Type::None),
+        ValueExpression::Value(SimpleValue::List(_)) => Some(Type::List),
+        ValueExpression::Value(SimpleValue::Map(_)) => Some(Type::Map),
+        ValueExpression::Negate(_) | ValueExpression::Arithmetic(..) => Some(Type::Number),
+        ValueExpression::Call(function, _) => Some(function.returns()).filter(|t| *t != Type::Any),
+    }
+}
+
+fn function_call<'a>(options: &'a Options) -> Parser<'a, u8, Spanned<ValueExpression>> {
+    let arg = || {
+        cut(
+            call(move || value_expression(options)),
+            EXPECTED_VALUE,
+            |_, _| "expected a function argument".to_string(),
+        ) - space()
+    };
+    let args = (-!rparen() * arg() + many(sym(b',') * space() * arg())).opt();
+    let close = cut(rparen(), EXPECTED_CLOSE_VALUES, |_, _| {
+        "expected `)` to close the function call".to_string()
+    });
+    let name = spanned(ident_char().repeat(1..).convert(String::from_utf8));
+    // A name directly followed by `(` can only be a function call
+    spanned((name - lparen() - space()) + args - close).map(|call| {
+        let (name, args) = call.node;
+        let args = match args {
+            Some((first, rest)) => [vec![first], rest].concat(),
+            None => vec![],
+        };
+        Spanned {
+            node: (name, args),
+            span: call.span,
+        }
+    }) >> move |call: Spanned<(Spanned<String>, Vec<Spanned<ValueExpression>>)>| {
+        let (name, args) = call.node;
+        let Some(function) = Function::resolve(&name.node, &options.functions) else {
+            return reject(name.span.start, format!("unknown function `{}`", name.node));
+        };
+        if !function.arity().contains(&args.len()) {
+            return reject(
+                name.span.start,
+                format!(
+                    "`{}` takes {} but {} given",
+                    function.name(),
+                    describe_arity(&function.arity()),
+                    match args.len() {
+                        1 => "1 was".to_string(),
+                        n => format!("{n} were"),
+                    }
+                ),
+            );
+        }
+        for (i, (arg, param)) in args.iter().zip(function.params()).enumerate() {
+            match static_type(&arg.node) {
+                Some(arg_type) if !param.accepts(arg_type) => {
+                    return reject(
+                        arg.span.start,
+                        format!(
+                            "`{}` expects a {param} for argument {} but got a {arg_type}",
+                            function.name(),
+                            i + 1
+                        ),
+                    )
+                }
+                _ => {}
+            }
+        }
+        let node = ValueExpression::Call(function, args);
+        empty().map(move |_| Spanned {
+            node: node.clone(),
+            span: call.span,
+        })
+    }
+}
+
+fn atom<'a>(options: &'a Options) -> Parser<'a, u8, Spanned<ValueExpression>> {
+    function_call(options)
+        | path_access()
+        | simple_value().map(|v| Spanned {
+            node: ValueExpression::Value(v.node),
+            span: v.span,
+        })
+        | spanned(lparen() * space() * call(move || value_expression(options)) - space() - rparen())
+            .map(|parenthesised| Spanned {
+                node: parenthesised.node.node,
+                span: parenthesised.span,
+            })
+}
+
+fn unary<'a>(options: &'a Options) -> Parser<'a, u8, Spanned<ValueExpression>> {
+    atom(options)
+        | spanned(
+            (sym(b'-') - space())
+                * cut(call(move || unary(options)), EXPECTED_VALUE, |_, _| {
+                    "expected a value after `-`".to_string()
+                }),
+        )
+        .map(|negated| Spanned {
+            node: ValueExpression::Negate(Box::new(negated.node)),
+            span: negated.span,
+        })
+}
+
+// A left-associative chain of `operand`s joined by `op`s
+fn arithmetic<'a>(
+    options: &'a Options,
+    operand: fn(&'a Options) -> Parser<'a, u8, Spanned<ValueExpression>>,
+    op: fn() -> Parser<'a, u8, ArithOp>,
+) -> Parser<'a, u8, Spanned<ValueExpression>> {
+    let rest = many(
+        (space() * spanned(op()) - space())
+            >> move |op: Spanned<ArithOp>| {
+                cut(operand(options), EXPECTED_VALUE, move |input, _| {
+                    format!("expected a value after `{}`", text(input, op.span))
+                })
+                .map(move |rhs| (op.node, rhs))
+            },
+    );
+    (operand(options) + rest).map(|(first, rest)| {
+        rest.into_iter().fold(first, |lhs, (op, rhs)| Spanned {
+            span: Span {
+                start: lhs.span.start,
+                end: rhs.span.end,
+            },
+            node: ValueExpression::Arithmetic(Box::new(lhs), op, Box::new(rhs)),
+        })
+    })
+}
+
+fn term<'a>(options: &'a Options) -> Parser<'a, u8, Spanned<ValueExpression>> {
+    arithmetic(options, unary, multiplicative_op)
+}
+
+fn value_expression<'a>(options: &'a Options) -> Parser<'a, u8, Spanned<ValueExpression>> {
+    arithmetic(options, term, additive_op)
+}
+
+fn value_group<'a>(options: &'a Options) -> Parser<'a, u8, Vec<Spanned<ValueExpression>>> {
+    // Once there's a comma this can only be a group of values, before that
+    // it could still be the start of a parenthesised condition
+    let rest = many(
+        (sym(b',') * space())
+            * cut(value_expression(options), EXPECTED_VALUE, |_, _| {
+                "expected a value after `,`".to_string()
+            })
+            - space(),
+    );
+    ((lparen() * space() * value_expression(options) - space()) + rest).convert(|(first, rest)| {
+        match (&first.node, rest.is_empty()) {
+            // `(x + 1)` is a parenthesised value rather than a group of one
+            (ValueExpression::Negate(_) | ValueExpression::Arithmetic(..), true) => {
+                Err("not a group")
+            }
+            _ => Ok([vec![first], rest].concat()),
+        }
+    }) >> |values: Vec<Spanned<ValueExpression>>| {
+        let close = if values.len() > 1 {
+            cut(rparen(), EXPECTED_CLOSE_VALUES, |_, _| {
+                "expected `)` to close the group of values".to_string()
+            })
+        } else {
+            // `(x) * 2` is arithmetic on a parenthesised value
+            rparen() - !(space() * (additive_op() | multiplicative_op()))
+        };
+        close.map(move |_| values.clone())
+    }
+}
+
+fn property_val<'a>(options: &'a Options) -> Parser<'a, u8, Spanned<PropertyVal>> {
+    spanned(
+        value_group(options).map(PropertyVal::Group)
+            | value_expression(options).map(PropertyVal::Value),
+    )
+}
+
+fn not<'a>() -> Parser<'a, u8, ()> {
+    ((seq(b"n") | seq(b"N")) + (seq(b"o") | seq(b"O")) + (seq(b"t") | seq(b"T")) - !ident_char())
+        .discard()
+        | (sym(b'!') - !sym(b'=')).discard()
+        | seq("¬".as_bytes()).discard()
 }
 
 fn and<'a>() -> Parser<'a, u8, u8> {
-    ((seq(b"a") | seq(b"A")) + (seq(b"n") | seq(b"N")) + (seq(b"d") | seq(b"D"))).map(|_| 0)
+    ((seq(b"a") | seq(b"A")) + (seq(b"n") | seq(b"N")) + (seq(b"d") | seq(b"D")) - !ident_char())
+        .map(|_| 0)
 }
 
 fn or<'a>() -> Parser<'a, u8, u8> {
-    ((seq(b"o") | seq(b"O")) + (seq(b"r") | seq(b"R"))).map(|_| 0)
+    ((seq(b"o") | seq(b"O")) + (seq(b"r") | seq(b"R")) - !ident_char()).map(|_| 0)
 }
 
 fn and_or<'a>() -> Parser<'a, u8, AndOr> {
     and().map(|_| AndOr::And) | or().map(|_| AndOr::Or)
 }
 
-fn boolean_condition<'a>() -> Parser<'a, u8, BooleanCondition> {
-    space()
-        * ((property_val() + binary_op() + property_val())
-            .map(|((lval, bin_op), rval)| BooleanCondition::Comparison(lval, bin_op, rval))
-            | (lparen() * call(boolean_expression) - rparen()).map(|boolean_expression| {
-                BooleanCondition::Group(Box::new(boolean_expression))
-            }))
-        - space()
+// Either kind of operator that can follow the left side of a comparison
+enum Operator {
+    Comparison(BinOp),
+    Match(MatchOp),
+}
+
+fn operator<'a>() -> Parser<'a, u8, Operator> {
+    match_op().map(Operator::Match) | binary_op().map(Operator::Comparison)
+}
+
+// A string literal compiled to a regex, reporting invalid regexes as parse errors
+fn pattern<'a>() -> Parser<'a, u8, Spanned<Regex>> {
+    spanned(str())
+        >> |pattern: Spanned<String>| match Regex::new(&pattern.node) {
+            Ok(regex) => empty().map(move |_| Spanned {
+                node: regex.clone(),
+                span: pattern.span,
+            }),
+            Err(err) => reject(pattern.span.start, regex_error(err)),
+        }
+}
+
+// regex's syntax errors span several lines and point into the pattern, so
+// only the description is kept
+fn regex_error(err: regex::Error) -> String {
+    let description = match &err {
+        regex::Error::Syntax(message) => message
+            .lines()
+            .find_map(|line| line.strip_prefix("error: "))
+            .unwrap_or(message)
+            .to_string(),
+        _ => err.to_string(),
+    };
+    format!("invalid regex: {description}")
 }
 
-fn boolean_expression<'a>() -> Parser<'a, u8, BooleanExpression> {
-    (boolean_condition() + (and_or()) + call(boolean_expression)).map(
-        |((boolean_condition, and_or_initial), boolean_expression)| BooleanExpression {
-            initial: boolean_condition,
-            conditions: vec![(
-                and_or_initial,
-                BooleanCondition::Group(Box::new(boolean_expression)),
-            )],
+fn comparison<'a>(options: &'a Options) -> Parser<'a, u8, BooleanCondition> {
+    // Only a comparison starts with a value, so after one has been parsed
+    // failures are reported rather than backtracking
+    (property_val(options) - space())
+        >> move |lval: Spanned<PropertyVal>| {
+            let lval_span = lval.span;
+            (cut(spanned(operator()), EXPECTED_OPERATOR, move |input, _| {
+                format!("expected an operator after `{}`", text(input, lval_span))
+            }) - space())
+                >> move |op: Spanned<Operator>| {
+                    let lval = lval.clone();
+                    let message = move |expected: &'static str| {
+                        move |input, _| {
+                            format!("expected {expected} after `{}`", text(input, op.span))
+                        }
+                    };
+                    match op.node {
+                        Operator::Comparison(bin_op) => {
+                            let bin_op = Spanned {
+                                node: bin_op,
+                                span: op.span,
+                            };
+                            cut(property_val(options), EXPECTED_VALUE, message("a value")).map(
+                                move |rval| {
+                                    BooleanCondition::Comparison(lval.clone(), bin_op, rval)
+                                },
+                            )
+                        }
+                        Operator::Match(match_op) => {
+                            let match_op = Spanned {
+                                node: match_op,
+                                span: op.span,
+                            };
+                            cut(pattern(), EXPECTED_PATTERN, message("a pattern")).map(
+                                move |pattern| {
+                                    BooleanCondition::Match(lval.clone(), match_op, pattern)
+                                },
+                            )
+                        }
+                    }
+                }
+        }
+}
+
+fn quantifier<'a>() -> Parser<'a, u8, Quantifier> {
+    seq(b"any").map(|_| Quantifier::Any)
+        | seq(b"all").map(|_| Quantifier::All)
+        | seq(b"none").map(|_| Quantifier::None)
+}
+
+fn quantified<'a>(options: &'a Options) -> Parser<'a, u8, BooleanCondition> {
+    let variable = spanned(ident_char().repeat(1..).convert(String::from_utf8));
+    // Until `in` this could still be a call to a function named `any`
+    let head = (quantifier() - lparen() - space()) + variable
+        - space()
+        - (seq(b"in") | seq("∈".as_bytes()))
+        - !ident_char()
+        - space();
+    let collection = cut(property_val(options), EXPECTED_VALUE, |_, _| {
+        "expected a collection after `in`".to_string()
+    }) - space();
+    let colon = cut(sym(b':'), EXPECTED_COLON, |_, _| {
+        "expected `:` after the collection".to_string()
+    }) - space();
+    let close = cut(rparen(), EXPECTED_CLOSE_GROUP, |_, _| {
+        "expected `)` to close the quantifier".to_string()
+    });
+    (head + collection - colon + call(move || boolean_expression(options)) - space() - close).map(
+        |(((quantifier, variable), collection), body)| {
+            BooleanCondition::Quantified(quantifier, variable, collection, Box::new(body))
         },
-    ) | boolean_condition().map(|boolean_condition| BooleanExpression {
-        initial: boolean_condition,
-        conditions: vec![],
+    )
+}
+
+fn boolean_condition<'a>(options: &'a Options) -> Parser<'a, u8, Spanned<BooleanCondition>> {
+    spanned(
+        ((not() - space()) * call(move || boolean_condition(options)))
+            .map(|boolean_condition| BooleanCondition::Not(Box::new(boolean_condition)))
+            | quantified(options)
+            | comparison(options)
+            | (lparen() * space() * call(move || boolean_expression(options)) - space()
+                + cut(rparen(), EXPECTED_CLOSE_GROUP, |_, _| {
+                    "expected `)` to close the group".to_string()
+                }))
+            .map(|(boolean_expression, _)| BooleanCondition::Group(Box::new(boolean_expression))),
+    )
+}
+
+fn boolean_expression<'a>(options: &'a Options) -> Parser<'a, u8, BooleanExpression> {
+    let initial = cut(boolean_condition(options), EXPECTED_CONDITION, |_, _| {
+        "expected a condition".to_string()
+    });
+    let conditions = many(
+        (space() * spanned(and_or()) - space())
+            >> move |and_or: Spanned<AndOr>| {
+                cut(
+                    boolean_condition(options),
+                    EXPECTED_CONDITION,
+                    move |input, _| {
+                        format!("expected a condition after `{}`", text(input, and_or.span))
+                    },
+                )
+                .map(move |cond| (and_or.node, cond))
+            },
+    );
+    (initial + conditions).map(move |(initial, conditions)| {
+        let initial = BooleanExpression::Condition(initial);
+        let mut rest = conditions
+            .into_iter()
+            .map(|(and_or, cond)| (and_or, BooleanExpression::Condition(cond)))
+            .peekable();
+        match options.precedence {
+            Precedence::Standard => climb(initial, &mut rest, 0),
+            Precedence::Boolrule => nest_right(initial, rest.collect()),
+        }
     })
 }
 
-pub fn parse<'a>(input: &str) -> Result<BooleanExpression, pom::Error> {
-    (space() * boolean_expression() - end()).parse(input.as_bytes())
+// Precedence climbing over a flat `a op b op c ...` chain
+fn climb<I: Iterator<Item = (AndOr, BooleanExpression)>>(
+    mut lhs: BooleanExpression,
+    rest: &mut Peekable<I>,
+    min_binding_power: u8,
+) -> BooleanExpression {
+    while let Some(and_or) = rest
+        .peek()
+        .map(|(and_or, _)| *and_or)
+        .filter(|and_or| and_or.binding_power() >= min_binding_power)
+    {
+        let (_, mut rhs) = rest.next().unwrap();
+        while rest
+            .peek()
+            .is_some_and(|(next, _)| next.binding_power() > and_or.binding_power())
+        {
+            rhs = climb(rhs, rest, and_or.binding_power() + 1);
+        }
+        lhs = BooleanExpression::Binary(Box::new(lhs), and_or, Box::new(rhs));
+    }
+    lhs
+}
+
+// `a op1 b op2 c` -> `a op1 (b op2 c)`
+fn nest_right(
+    initial: BooleanExpression,
+    conditions: Vec<(AndOr, BooleanExpression)>,
+) -> BooleanExpression {
+    let mut operands = vec![initial];
+    let mut and_ors = vec![];
+    for (and_or, cond) in conditions {
+        and_ors.push(and_or);
+        operands.push(cond);
+    }
+    let mut result = operands.pop().unwrap();
+    while let Some(and_or) = and_ors.pop() {
+        let lhs = operands.pop().unwrap();
+        result = BooleanExpression::Binary(Box::new(lhs), and_or, Box::new(result));
+    }
+    result
+}
+
+pub fn parse(input: &str, options: &Options) -> Result<BooleanExpression, Diagnostic> {
+    let end = cut(end(), EXPECTED_END, |input, position| {
+        format!("unexpected {}", describe_token(input, position))
+    });
+    (space() * boolean_expression(options) - space() - end)
+        .parse(input.as_bytes())
+        .map_err(|err| diagnostic(input, err))
+}
+
+// Unpacks the errors produced by `cut`
+fn diagnostic(input: &str, err: pom::Error) -> Diagnostic {
+    match err {
+        pom::Error::Expect {
+            message,
+            position,
+            inner,
+        } => {
+            let expected = match *inner {
+                pom::Error::Custom { message, .. } => message
+                    .split('\n')
+                    .filter(|s| !s.is_empty())
+                    .map(|s| s.to_string())
+                    .collect(),
+                _ => vec![],
+            };
+            Diagnostic::new(input, position, message, expected)
+        }
+        pom::Error::Mismatch { position, .. }
+        | pom::Error::Conversion { position, .. }
+        | pom::Error::Custom { position, .. } => Diagnostic::new(
+            input,
+            position,
+            format!("unexpected {}", describe_token(input.as_bytes(), position)),
+            vec![],
+        ),
+        pom::Error::Incomplete => Diagnostic::new(
+            input,
+            input.len(),
+            "unexpected end of input".to_string(),
+            vec![],
+        ),
+    }
 }
 
 #[test]
@@ -213,26 +918,396 @@ fn test_parse() {
         "x in (5, 6, 7)",
         "(3, 4) not∩ (3, 4, 5)",
         "x1b < 3",
+        "not 5 > 3",
+        "not (1=1 or 2=2) and 3 = 3",
+        "!(x in (5, 6, 7))",
+        "¬ foo.bar is none",
+        "not not true == true",
+        "nothing > 3",
+        "order.total - order.discount > 100",
+        "score * 1.5 >= threshold",
+        "-x < -3",
+        "5 - -3 = 8",
+        "(a + b) * 2 > 10",
+        "(a) * 2 > 10",
+        "-(a % 2) = 0 and (1 + 2) in (3, 4 / 2)",
+        "x-1 = 2",
+        "lower(user.email) ends_with \"@corp.com\"",
+        "round( price * 1.2 , 2 ) <= max(a, b)",
+        "len(trim(name)) > 0 and name starts_with \"A\"",
+        "tags contains \"x\"",
+        "user.email =~ \"^[a-z]+@example\\.com$\"",
+        "name matches \"(?i)^bob\" and name !~ \"y$\"",
+        "path like \"/api/%\" or filename glob \"*.csv\"",
+        "name ilike \"a\\_b%\" and name iglob \"[a-z]*\"",
+        "items[0].price > 1 and items[-1] = 2 and \"x\" in items[ * ].sku",
+        "a.b[0][1].c.d[*] ⊆ (1, 2)",
+        "any(item in order.items: item.price > 100)",
+        "all( a ∈ addresses : a.country in (\"DE\", \"FR\") and not none(t in a.tags: t = 1) )",
     ];
 
     let mut pass = true;
     for expr in valid_exprs.iter() {
-        match parse(expr) {
-            Ok(_) => (),
-            Err(e) => {
-                println!("{expr}");
-                match e {
-                    pom::Error::Mismatch { message, position } => {
-                        let spaces = " ".repeat(position);
-                        println!("{} {message}", format!("{}{}", spaces, "^"))
-                    }
-                    _ => {
-                        println!("{e}")
-                    }
-                }
-                pass = false;
+        if let Err(e) = parse(expr, &Options::default()) {
+            println!("{e}");
+            pass = false;
+        }
+    }
+    assert!(pass);
+
+    // (expression, message, span of the offending token, expected tokens)
+    let invalid_exprs = [
+        ("", "expected a condition", (0, 0), EXPECTED_CONDITION),
+        ("x >", "expected a value after `>`", (3, 3), EXPECTED_VALUE),
+        (
+            "x == and y = 2",
+            "expected a value after `==`",
+            (5, 8),
+            EXPECTED_VALUE,
+        ),
+        (
+            "x 5",
+            "expected an operator after `x`",
+            (2, 3),
+            EXPECTED_OPERATOR,
+        ),
+        (
+            "(1, 2) 5",
+            "expected an operator after `(1, 2)`",
+            (7, 8),
+            EXPECTED_OPERATOR,
+        ),
+        (
+            "x = 1 and",
+            "expected a condition after `and`",
+            (9, 9),
+            EXPECTED_CONDITION,
+        ),
+        (
+            "x = 1 OR > 2",
+            "expected a condition after `OR`",
+            (9, 10),
+            EXPECTED_CONDITION,
+        ),
+        (
+            "(x = 1 and y = 2",
+            "expected `)` to close the group",
+            (16, 16),
+            EXPECTED_CLOSE_GROUP,
+        ),
+        (
+            "x in (1, 2",
+            "expected `)` to close the group of values",
+            (10, 10),
+            EXPECTED_CLOSE_VALUES,
+        ),
+        (
+            "x in (1, )",
+            "expected a value after `,`",
+            (9, 10),
+            EXPECTED_VALUE,
+        ),
+        ("x = 1 y = 2", "unexpected `y`", (6, 7), EXPECTED_END),
+        ("x = 1 andy = 2", "unexpected `andy`", (6, 10), EXPECTED_END),
+        ("x = \"abc", "unterminated string", (4, 5), EXPECTED_QUOTE),
+        (
+            "not (x > 1",
+            "expected `)` to close the group",
+            (10, 10),
+            EXPECTED_CLOSE_GROUP,
+        ),
+        (
+            "x = 1 +",
+            "expected a value after `+`",
+            (7, 7),
+            EXPECTED_VALUE,
+        ),
+        (
+            "x * > 2",
+            "expected a value after `*`",
+            (4, 5),
+            EXPECTED_VALUE,
+        ),
+        (
+            "- > 2",
+            "expected a value after `-`",
+            (2, 3),
+            EXPECTED_VALUE,
+        ),
+        (
+            "x ≥ ≥",
+            "expected a value after `≥`",
+            (6, 9),
+            EXPECTED_VALUE,
+        ),
+        ("foo(x) = 1", "unknown function `foo`", (0, 3), &[]),
+        (
+            "lower(a, b) = 1",
+            "`lower` takes 1 argument but 2 were given",
+            (0, 5),
+            &[],
+        ),
+        (
+            "max() = 1",
+            "`max` takes at least 1 argument but 0 were given",
+            (0, 3),
+            &[],
+        ),
+        (
+            "abs(x = 1",
+            "expected `)` to close the function call",
+            (6, 7),
+            EXPECTED_CLOSE_VALUES,
+        ),
+        ("x =~ \"(a\"", "invalid regex: unclosed group", (5, 6), &[]),
+        (
+            "any(x in : x > 1)",
+            "expected a collection after `in`",
+            (9, 10),
+            EXPECTED_VALUE,
+        ),
+        (
+            "any(x in items x > 1)",
+            "expected `:` after the collection",
+            (15, 16),
+            EXPECTED_COLON,
+        ),
+        (
+            "all(x in items: x > 1",
+            "expected `)` to close the quantifier",
+            (21, 21),
+            EXPECTED_CLOSE_GROUP,
+        ),
+        ("any(x) = 1", "unknown function `any`", (0, 3), &[]),
+        (
+            "items[a] = 1",
+            "expected an index after `[`",
+            (6, 7),
+            EXPECTED_INDEX,
+        ),
+        (
+            "items[0 = 1",
+            "expected `]` to close the index",
+            (8, 9),
+            EXPECTED_CLOSE_INDEX,
+        ),
+        (
+            "x !~ y",
+            "expected a pattern after `!~`",
+            (5, 6),
+            EXPECTED_PATTERN,
+        ),
+    ];
+    for (expr, message, (start, end), expected) in invalid_exprs.iter() {
+        let e = parse(expr, &Options::default()).unwrap_err();
+        assert_eq!(e.message, *message, "{expr}");
+        assert_eq!(
+            e.span,
+            Span {
+                start: *start,
+                end: *end
+            },
+            "{expr}"
+        );
+        assert_eq!(e.expected, expected.to_vec(), "{expr}");
+    }
+}
+
+#[test]
+fn test_spans() {
+    let expr = "x > 1 and not (y, 2) ∩ (\"a\")";
+    let parsed = parse(expr, &Options::default()).unwrap();
+    let BooleanExpression::Binary(lhs, AndOr::And, rhs) = parsed else {
+        panic!("expected `and`");
+    };
+    let BooleanExpression::Condition(cond) = *lhs else {
+        panic!("expected a condition");
+    };
+    assert_eq!(&expr[cond.span.start..cond.span.end], "x > 1");
+    let BooleanCondition::Comparison(lval, bin_op, rval) = cond.node else {
+        panic!("expected a comparison");
+    };
+    assert_eq!(&expr[lval.span.start..lval.span.end], "x");
+    assert_eq!(&expr[bin_op.span.start..bin_op.span.end], ">");
+    assert_eq!(&expr[rval.span.start..rval.span.end], "1");
+
+    let BooleanExpression::Condition(cond) = *rhs else {
+        panic!("expected a condition");
+    };
+    assert_eq!(
+        &expr[cond.span.start..cond.span.end],
+        "not (y, 2) ∩ (\"a\")"
+    );
+    let BooleanCondition::Not(cond) = cond.node else {
+        panic!("expected `not`");
+    };
+    assert_eq!(&expr[cond.span.start..cond.span.end], "(y, 2) ∩ (\"a\")");
+    let BooleanCondition::Comparison(lval, bin_op, rval) = cond.node else {
+        panic!("expected a comparison");
+    };
+    assert_eq!(&expr[lval.span.start..lval.span.end], "(y, 2)");
+    assert_eq!(&expr[bin_op.span.start..bin_op.span.end], "∩");
+    assert_eq!(&expr[rval.span.start..rval.span.end], "(\"a\")");
+    let PropertyVal::Group(values) = lval.node else {
+        panic!("expected a group");
+    };
+    let spans: Vec<&str> = values
+        .iter()
+        .map(|v| &expr[v.span.start..v.span.end])
+        .collect();
+    assert_eq!(spans, vec!["y", "2"]);
+}
+
+#[test]
+fn test_keywords() {
+    let value = |input: &str| {
+        (simple_value() - end())
+            .parse(input.as_bytes())
+            .map(|v| v.node)
+    };
+    assert!(matches!(value("true"), Ok(SimpleValue::Bool(true))));
+    assert!(matches!(value("FALSE"), Ok(SimpleValue::Bool(false))));
+    assert!(matches!(value("None"), Ok(SimpleValue::None)));
+
+    // Keywords only match whole words, anything longer is a property path
+    for path in [
+        "trueish",
+        "falsehood",
+        "nonempty",
+        "android",
+        "orders",
+        "notable",
+    ] {
+        match value(path) {
+            Ok(SimpleValue::PropertyPath(p)) => assert_eq!(p, vec![path.to_string()]),
+            v => panic!("{path}: expected a property path, got {v:?}"),
+        }
+    }
+    assert!(parse("notable = 1 andy = 2", &Options::default()).is_err());
+    assert!(parse("not(x = 1) or(y = 2)", &Options::default()).is_ok());
+
+    // `and` and `or` can't start a property path
+    assert!(value("and").is_err());
+    assert!(value("or.x").is_err());
+    let e = parse("x = or y = 2", &Options::default()).unwrap_err();
+    assert_eq!(e.message, "expected a value after `=`");
+}
+
+#[test]
+fn test_precedence() {
+    fn shape(boolean_expression: &BooleanExpression) -> String {
+        match boolean_expression {
+            BooleanExpression::Condition(cond) => match &cond.node {
+                BooleanCondition::Comparison(lval, _, _) => match &lval.node {
+                    PropertyVal::Value(Spanned {
+                        node: ValueExpression::Value(SimpleValue::PropertyPath(p)),
+                        ..
+                    }) => p.join("."),
+                    _ => "?".to_string(),
+                },
+                _ => "?".to_string(),
+            },
+            BooleanExpression::Binary(lhs, and_or, rhs) => {
+                let and_or = match and_or {
+                    AndOr::And => "and",
+                    AndOr::Or => "or",
+                };
+                format!("({} {and_or} {})", shape(lhs), shape(rhs))
             }
+        }
+    }
+
+    let exprs = [
+        ("a = 1", "a", "a"),
+        ("a = 1 and b = 1", "(a and b)", "(a and b)"),
+        (
+            "a = 1 or b = 1 and c = 1",
+            "(a or (b and c))",
+            "(a or (b and c))",
+        ),
+        (
+            "a = 1 and b = 1 or c = 1",
+            "((a and b) or c)",
+            "(a and (b or c))",
+        ),
+        (
+            "a = 1 or b = 1 or c = 1",
+            "((a or b) or c)",
+            "(a or (b or c))",
+        ),
+        (
+            "a = 1 and b = 1 or c = 1 and d = 1 or e = 1",
+            "(((a and b) or (c and d)) or e)",
+            "(a and (b or (c and (d or e))))",
+        ),
+    ];
+    for (expr, standard, boolrule) in exprs.iter() {
+        let parsed = parse(expr, &Options::default()).unwrap();
+        assert_eq!(shape(&parsed), *standard, "{expr}");
+        let parsed = parse(
+            expr,
+            &Options {
+                precedence: Precedence::Boolrule,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(shape(&parsed), *boolrule, "{expr}");
+    }
+}
+
+#[test]
+fn test_arithmetic() {
+    fn shape(value_expression: &ValueExpression) -> String {
+        match value_expression {
+            ValueExpression::Value(SimpleValue::PropertyPath(p)) => p.join("."),
+            ValueExpression::Value(v) => format!("{v:?}"),
+            ValueExpression::Negate(operand) => format!("-{}", shape(&operand.node)),
+            ValueExpression::Access(path, accessors) => format!("{}{accessors:?}", path.join(".")),
+            ValueExpression::Call(function, args) => {
+                let args: Vec<_> = args.iter().map(|arg| shape(&arg.node)).collect();
+                format!("{}({})", function.name(), args.join(", "))
+            }
+            ValueExpression::Arithmetic(lhs, op, rhs) => {
+                format!("({} {op:?} {})", shape(&lhs.node), shape(&rhs.node))
+            }
+        }
+    }
+    fn lval(expr: &str) -> PropertyVal {
+        let BooleanExpression::Condition(cond) = parse(expr, &Options::default()).unwrap() else {
+            panic!("expected a condition");
         };
+        let BooleanCondition::Comparison(lval, _, _) = cond.node else {
+            panic!("expected a comparison");
+        };
+        lval.node
     }
-    assert!(pass);
+
+    let exprs = [
+        ("a + b * c = 1", "(a Add (b Mul c))"),
+        ("a * b + c = 1", "((a Mul b) Add c)"),
+        ("a - b - c = 1", "((a Sub b) Sub c)"),
+        ("a / b % c = 1", "((a Div b) Rem c)"),
+        ("(a + b) * c = 1", "((a Add b) Mul c)"),
+        ("-a * -3 = 1", "(-a Mul Number(-3.0))"),
+        ("- -a = 1", "--a"),
+        ("(a) - 1 = 1", "(a Sub Number(1.0))"),
+        ("(-a) = 1", "-a"),
+        ("abs(a - b) * 2 = 1", "(abs((a Sub b)) Mul Number(2.0))"),
+        ("max(a, -b, min(c)) = 1", "max(a, -b, min(c))"),
+        (
+            "a.b[-1][*].c + 1 = 1",
+            "(a.b[Index(-1), Wildcard, Field(\"c\")] Add Number(1.0))",
+        ),
+    ];
+    for (expr, expected) in exprs.iter() {
+        match lval(expr) {
+            PropertyVal::Value(v) => assert_eq!(shape(&v.node), *expected, "{expr}"),
+            PropertyVal::Group(_) => panic!("{expr} shouldn't be a group"),
+        }
+    }
+
+    // A single parenthesised value is still a group
+    assert!(matches!(lval("(a) ∩ (a, b)"), PropertyVal::Group(v) if v.len() == 1));
+    assert!(matches!(lval("(a + 1, b) ⊆ (a, b)"), PropertyVal::Group(v) if v.len() == 2));
 }
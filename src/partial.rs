@@ -0,0 +1,221 @@
+use crate::{
+    evaluator::{Conditions, Truth},
+    parser::{AndOr, BooleanCondition, BooleanExpression, Spanned},
+    CoolRule, EvalError, EvalErrorKind, Evaluation,
+};
+
+/// The result of [`CoolRule::partial_eval`].
+pub enum Partial {
+    /// The known context decides the rule.
+    Decided(bool),
+    /// The rule that's left to evaluate once the rest of the context is known.
+    Residual(Box<CoolRule>),
+}
+
+// What's left of part of an expression after folding, along with whether a
+// residual holds a condition that failed with the known context
+enum Folded<T> {
+    Known(bool),
+    Residual(T, bool),
+}
+
+// Conditions the known context decides become constants, and constants are
+// folded into the `and`/`or`/`not` around them. Conditions that read unknown
+// paths, or that fail, are kept for when the rest of the context is known.
+fn fold_condition(
+    condition: &Spanned<BooleanCondition>,
+    conditions: &Conditions,
+) -> Folded<Spanned<BooleanCondition>> {
+    match &condition.node {
+        BooleanCondition::Group(expr) => match fold_expression(expr, conditions) {
+            Folded::Known(b) => Folded::Known(b),
+            // A single condition doesn't need its parentheses
+            Folded::Residual(BooleanExpression::Condition(cond), failed) => {
+                Folded::Residual(cond, failed)
+            }
+            Folded::Residual(expr, failed) => Folded::Residual(
+                Spanned {
+                    node: BooleanCondition::Group(Box::new(expr)),
+                    span: condition.span,
+                },
+                failed,
+            ),
+        },
+        BooleanCondition::Not(cond) => match fold_condition(cond, conditions) {
+            Folded::Known(b) => Folded::Known(!b),
+            Folded::Residual(cond, failed) => Folded::Residual(
+                Spanned {
+                    node: BooleanCondition::Not(Box::new(cond)),
+                    span: condition.span,
+                },
+                failed,
+            ),
+        },
+        _ => match conditions.eval(condition) {
+            Ok(Truth::True) => Folded::Known(true),
+            Ok(Truth::False) => Folded::Known(false),
+            Ok(Truth::Unknown)
+            | Err(EvalError {
+                kind: EvalErrorKind::MissingVariable { .. },
+                ..
+            }) => Folded::Residual(condition.clone(), false),
+            Err(_) => Folded::Residual(condition.clone(), true),
+        },
+    }
+}
+
+fn fold_expression(
+    boolean_expression: &BooleanExpression,
+    conditions: &Conditions,
+) -> Folded<BooleanExpression> {
+    match boolean_expression {
+        BooleanExpression::Condition(cond) => match fold_condition(cond, conditions) {
+            Folded::Known(b) => Folded::Known(b),
+            Folded::Residual(cond, failed) => {
+                Folded::Residual(BooleanExpression::Condition(cond), failed)
+            }
+        },
+        BooleanExpression::Binary(lhs, and_or, rhs) => {
+            // `false and x` and `true or x` are decided, while `true and x`
+            // and `false or x` are just `x`
+            let decides = |b: bool| (*and_or == AndOr::And) != b;
+            let eager = conditions.options.evaluation == Evaluation::Eager;
+            let (lhs, lhs_failed) = match fold_expression(lhs, conditions) {
+                // Eager evaluation still reaches the right, so a failure there
+                // has to be kept for the error to surface
+                Folded::Known(b) if decides(b) && eager => {
+                    return match fold_expression(rhs, conditions) {
+                        Folded::Residual(rhs, true) => Folded::Residual(rhs, true),
+                        _ => Folded::Known(b),
+                    }
+                }
+                Folded::Known(b) if decides(b) => return Folded::Known(b),
+                Folded::Known(_) => return fold_expression(rhs, conditions),
+                Folded::Residual(lhs, failed) => (lhs, failed),
+            };
+            match fold_expression(rhs, conditions) {
+                // A failure on the left is reached before the right decides
+                // anything, so both are kept for the error to surface
+                Folded::Known(b) if decides(b) && lhs_failed => Folded::Residual(
+                    BooleanExpression::Binary(Box::new(lhs), *and_or, rhs.clone()),
+                    true,
+                ),
+                Folded::Known(b) if decides(b) => Folded::Known(b),
+                Folded::Known(_) => Folded::Residual(lhs, lhs_failed),
+                Folded::Residual(rhs, rhs_failed) => Folded::Residual(
+                    BooleanExpression::Binary(Box::new(lhs), *and_or, Box::new(rhs)),
+                    lhs_failed || rhs_failed,
+                ),
+            }
+        }
+    }
+}
+
+pub fn partial_eval(rule: &CoolRule, conditions: &Conditions) -> Partial {
+    let boolean_expression = match fold_expression(&rule.boolean_expression, conditions) {
+        Folded::Known(b) => return Partial::Decided(b),
+        // Nor does the whole rule need them
+        Folded::Residual(
+            BooleanExpression::Condition(Spanned {
+                node: BooleanCondition::Group(expr),
+                ..
+            }),
+            _,
+        ) => *expr,
+        Folded::Residual(expr, _) => expr,
+    };
+    Partial::Residual(Box::new(CoolRule::compiled(
+        boolean_expression,
+        rule.options.clone(),
+    )))
+}
+
+#[test]
+fn test_partial_eval() {
+    use crate::{new, Value};
+    use std::collections::HashMap;
+
+    let known = HashMap::from([
+        (vec!["region"], Value::Str("eu".to_string())),
+        (vec!["tier"], Value::Number(2.0)),
+    ]);
+    let exprs = [
+        ("region = \"eu\"", Ok(true)),
+        ("region = \"us\" and amount > 1", Ok(false)),
+        ("amount > 1 and region = \"us\"", Ok(false)),
+        ("amount > 1 or region = \"eu\"", Ok(true)),
+        ("any(x in (1, 2): x = tier)", Ok(true)),
+        ("region = \"us\" or amount > 1", Err("amount > 1")),
+        ("region = \"eu\" and amount > 1", Err("amount > 1")),
+        (
+            "not (region = \"eu\" and amount > 1)",
+            Err("not amount > 1"),
+        ),
+        (
+            "(region = \"eu\" or a = 1) and (b = 1 or c = 2)",
+            Err("b = 1 or c = 2"),
+        ),
+        (
+            "a = 1 and (b = 2 or region = \"us\") and c = 3",
+            Err("a = 1 and b = 2 and c = 3"),
+        ),
+        ("(a = 1 or tier > 5) and (b = 1 or tier < 5)", Err("a = 1")),
+        (
+            "any(x in xs: x = tier) or tier = 3",
+            Err("any(x in xs: x = tier)"),
+        ),
+        ("tier / 0 = 1 and region = \"eu\"", Err("tier / 0 = 1")),
+        (
+            "tier / 0 = 1 and region = \"us\"",
+            Err("tier / 0 = 1 and region = \"us\""),
+        ),
+        (
+            "(amount > 1 or tier / 0 = 1) and region = \"us\"",
+            Err("(amount > 1 or tier / 0 = 1) and region = \"us\""),
+        ),
+        ("a = 1 and region = \"us\"", Ok(false)),
+        ("amount > tier", Err("amount > tier")),
+        ("region = \"us\" and tier / 0 = 1", Ok(false)),
+    ];
+    let eager = [
+        ("region = \"us\" and tier / 0 = 1", Err("tier / 0 = 1")),
+        ("region = \"eu\" or tier / 0 = 1", Err("tier / 0 = 1")),
+        ("region = \"us\" and amount > 1", Ok(false)),
+        (
+            "(region = \"us\" and tier / 0 = 1) or amount > 1",
+            Err("tier / 0 = 1 or amount > 1"),
+        ),
+    ];
+    let full = HashMap::from([
+        (vec!["region"], Value::Str("eu".to_string())),
+        (vec!["tier"], Value::Number(2.0)),
+        (vec!["amount"], Value::Number(5.0)),
+        (vec!["a"], Value::Number(1.0)),
+        (vec!["b"], Value::Number(2.0)),
+        (vec!["c"], Value::Number(3.0)),
+        (vec!["xs"], Value::List(vec![Value::Number(2.0)])),
+    ]);
+    let exprs = exprs.iter().map(|row| (Evaluation::ShortCircuit, row));
+    for (evaluation, (expr, expected)) in
+        exprs.chain(eager.iter().map(|row| (Evaluation::Eager, row)))
+    {
+        let mut rule = new(expr).unwrap();
+        rule.set_evaluation(evaluation);
+        match (rule.partial_eval(&known), expected) {
+            (Partial::Decided(b), Ok(expected)) => assert_eq!(b, *expected, "{expr}"),
+            (Partial::Residual(residual), Err(expected)) => {
+                assert_eq!(residual.to_string(), *expected, "{expr}");
+                // The residual rule gives the same result once everything is known
+                assert_eq!(
+                    residual.test_with_context(&full).ok(),
+                    rule.test_with_context(&full).ok(),
+                    "{expr}"
+                );
+            }
+            (Partial::Decided(b), _) => panic!("{expr} shouldn't be decided, got {b}"),
+            (Partial::Residual(residual), _) => {
+                panic!("{expr} should be decided, got {residual}")
+            }
+        }
+    }
+}
@@ -0,0 +1,344 @@
+use crate::Value;
+use std::{collections::HashMap, future::Future};
+
+/// Supplies the values of property paths as a rule needs them, e.g. from a
+/// database or cache, rather than all up front.
+///
+/// [`CoolRule::test_with_provider`](crate::CoolRule::test_with_provider) only
+/// asks for the paths it reaches and asks for each at most once per
+/// evaluation, so `get` can be expensive.
+///
+/// # Examples
+///
+/// ```
+/// use coolrule::{ContextProvider, Value};
+///
+/// // Pretend these come from a feature flag service
+/// struct Flags;
+///
+/// impl ContextProvider for Flags {
+///     fn get(&self, path: &[&str]) -> Option<Value> {
+///         match path {
+///             ["flags", name] => Some(Value::Bool(name.starts_with("beta_"))),
+///             _ => None,
+///         }
+///     }
+/// }
+///
+/// let expr = coolrule::new("flags.beta_search = true and flags.dark_mode = false").unwrap();
+/// assert!(expr.test_with_provider(&Flags).unwrap());
+/// ```
+pub trait ContextProvider {
+    /// The value of `path`, e.g. `["user", "id"]` for `user.id`, or `None` if
+    /// there isn't one.
+    fn get(&self, path: &[&str]) -> Option<Value>;
+}
+
+/// Like [`ContextProvider`] but fetches values asynchronously, e.g. from
+/// another service, for [`CoolRule::test_async`](crate::CoolRule::test_async).
+///
+/// Only the paths a rule reaches are fetched. Paths needed by the same
+/// condition, like both sides of a comparison, are fetched concurrently,
+/// while a path behind a short-circuiting `and`/`or` waits until it's known
+/// to be reached.
+///
+/// # Examples
+///
+/// ```
+/// use coolrule::{AsyncContextProvider, Value};
+/// use std::future::Future;
+///
+/// // Pretend this is a client for a user service
+/// struct Users;
+///
+/// impl AsyncContextProvider for Users {
+///     fn get(&self, path: &[&str]) -> impl Future<Output = Option<Value>> + Send {
+///         let value = match path {
+///             ["user", "age"] => Some(Value::Number(36.0)),
+///             _ => None,
+///         };
+///         async move { value }
+///     }
+/// }
+///
+/// # tokio::runtime::Builder::new_current_thread().build().unwrap().block_on(async {
+/// let expr = coolrule::new("user.age >= 18").unwrap();
+/// assert!(expr.test_async(&Users).await.unwrap());
+/// # });
+/// ```
+pub trait AsyncContextProvider {
+    /// The value of `path`, e.g. `["user", "id"]` for `user.id`, or `None` if
+    /// there isn't one.
+    fn get(&self, path: &[&str]) -> impl Future<Output = Option<Value>> + Send;
+}
+
+// Finds the longest prefix of the path that's a key and looks up the rest of
+// the path in nested maps, so `foo.bar` can be a `["foo", "bar"]` key or a
+// `bar` entry in a map under `["foo"]`
+impl ContextProvider for HashMap<Vec<&str>, Value> {
+    fn get(&self, path: &[&str]) -> Option<Value> {
+        (1..=path.len())
+            .rev()
+            .find_map(|prefix| walk(self.get(&path[..prefix])?, &path[prefix..]))
+            .cloned()
+    }
+}
+
+impl AsyncContextProvider for HashMap<Vec<&str>, Value> {
+    fn get(&self, path: &[&str]) -> impl Future<Output = Option<Value>> + Send {
+        std::future::ready(ContextProvider::get(self, path))
+    }
+}
+
+impl<T: ContextProvider + ?Sized> ContextProvider for &T {
+    fn get(&self, path: &[&str]) -> Option<Value> {
+        (**self).get(path)
+    }
+}
+
+// Follows `segments` through nested maps
+fn walk<'a>(mut value: &'a Value, segments: &[&str]) -> Option<&'a Value> {
+    for segment in segments {
+        match value {
+            Value::Map(map) => value = map.get(*segment)?,
+            _ => return None,
+        }
+    }
+    Some(value)
+}
+
+#[test]
+fn test_provider() {
+    use crate::new;
+    use std::cell::RefCell;
+
+    // Records which paths were asked for
+    struct Recorder {
+        values: HashMap<Vec<&'static str>, Value>,
+        asked: RefCell<Vec<String>>,
+    }
+
+    impl ContextProvider for Recorder {
+        fn get(&self, path: &[&str]) -> Option<Value> {
+            self.asked.borrow_mut().push(path.join("."));
+            self.values.get(path).cloned()
+        }
+    }
+
+    let exprs = [
+        ("a > 1 and a < 10 and a != 5", Ok(true), vec!["a"]),
+        ("a = 1 and b = 2", Ok(false), vec!["a"]),
+        ("a = 3 or b.c = 2", Ok(true), vec!["a"]),
+        ("b.c = 4 and a in (b.c, 3)", Ok(true), vec!["b.c", "a"]),
+        ("any(x in xs: x = a)", Ok(true), vec!["xs", "a"]),
+        ("missing is none or missing = 1", Err(()), vec!["missing"]),
+    ];
+    for (expr, expected, asked) in exprs.iter() {
+        let recorder = Recorder {
+            values: HashMap::from([
+                (vec!["a"], Value::Number(3.0)),
+                (vec!["b", "c"], Value::Number(4.0)),
+                (
+                    vec!["xs"],
+                    Value::List(vec![Value::Number(1.0), Value::Number(3.0)]),
+                ),
+            ]),
+            asked: RefCell::new(vec![]),
+        };
+        let result = new(expr).unwrap().test_with_provider(&recorder);
+        assert_eq!(result.map_err(|_| ()), *expected, "{expr}");
+        assert_eq!(*recorder.asked.borrow(), *asked, "{expr}");
+    }
+
+    let context = HashMap::from([
+        (vec!["a", "b"], Value::Number(1.0)),
+        (
+            vec!["a"],
+            Value::Map(HashMap::from([("c".to_string(), Value::Number(2.0))])),
+        ),
+    ]);
+    assert_eq!(
+        ContextProvider::get(&context, &["a", "b"]),
+        Some(Value::Number(1.0))
+    );
+    assert_eq!(
+        ContextProvider::get(&context, &["a", "c"]),
+        Some(Value::Number(2.0))
+    );
+    assert_eq!(ContextProvider::get(&context, &["a", "d"]), None);
+}
+
+#[test]
+fn test_async_provider() {
+    use crate::{new, new_with_options, Evaluation, Functions, Options, Type};
+    use std::sync::{Arc, Mutex};
+
+    // Records which paths were fetched together
+    struct Service {
+        values: HashMap<Vec<&'static str>, Value>,
+        in_flight: Mutex<usize>,
+        rounds: Mutex<Vec<Vec<String>>>,
+    }
+
+    impl AsyncContextProvider for Service {
+        fn get(&self, path: &[&str]) -> impl Future<Output = Option<Value>> + Send {
+            let path = path.join(".");
+            let value = self.values.iter().find_map(|(k, v)| {
+                if k.join(".") == path {
+                    Some(v.clone())
+                } else {
+                    None
+                }
+            });
+            async move {
+                {
+                    let mut in_flight = self.in_flight.lock().unwrap();
+                    let mut rounds = self.rounds.lock().unwrap();
+                    if *in_flight == 0 {
+                        rounds.push(vec![]);
+                    }
+                    rounds.last_mut().unwrap().push(path);
+                    *in_flight += 1;
+                }
+                tokio::task::yield_now().await;
+                *self.in_flight.lock().unwrap() -= 1;
+                value
+            }
+        }
+    }
+
+    let exprs = [
+        (
+            "a = 3 and b.c = 4",
+            Evaluation::ShortCircuit,
+            Ok(true),
+            vec![vec!["a"], vec!["b.c"]],
+        ),
+        (
+            "a = 1 and b.c = 4",
+            Evaluation::ShortCircuit,
+            Ok(false),
+            vec![vec!["a"]],
+        ),
+        (
+            "a = 1 and b.c = 4",
+            Evaluation::Eager,
+            Ok(false),
+            vec![vec!["a", "b.c"]],
+        ),
+        (
+            "a in (b.c, 3) and a > 1",
+            Evaluation::ShortCircuit,
+            Ok(true),
+            vec![vec!["a", "b.c"]],
+        ),
+        (
+            "lower(s) = \"x\" or len(xs) = a",
+            Evaluation::ShortCircuit,
+            Ok(false),
+            vec![vec!["s"], vec!["xs", "a"]],
+        ),
+        (
+            "any(x in xs: x = a)",
+            Evaluation::ShortCircuit,
+            Ok(true),
+            vec![vec!["xs"], vec!["a"]],
+        ),
+        (
+            "a = 3 and missing = 1",
+            Evaluation::ShortCircuit,
+            Err(()),
+            vec![vec!["a"], vec!["missing"]],
+        ),
+        ("1 = 1 or a = 1", Evaluation::ShortCircuit, Ok(true), vec![]),
+    ];
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .unwrap();
+    for (expr, evaluation, expected, rounds) in exprs.iter() {
+        let service = Service {
+            values: HashMap::from([
+                (vec!["a"], Value::Number(3.0)),
+                (vec!["b", "c"], Value::Number(4.0)),
+                (vec!["s"], Value::Str("Y".to_string())),
+                (
+                    vec!["xs"],
+                    Value::List(vec![Value::Number(1.0), Value::Number(3.0)]),
+                ),
+            ]),
+            in_flight: Mutex::new(0),
+            rounds: Mutex::new(vec![]),
+        };
+        let mut rule = new(expr).unwrap();
+        rule.set_evaluation(*evaluation);
+        let result = runtime.block_on(rule.test_async(&service));
+        assert_eq!(result.map_err(|_| ()), *expected, "{expr}");
+        assert_eq!(*service.rounds.lock().unwrap(), *rounds, "{expr}");
+    }
+
+    // The same results as evaluating synchronously
+    let context = HashMap::from([(vec!["a"], Value::Number(3.0))]);
+    for expr in ["a = 3", "a > 5 or (a < 5 and a != 4)", "b = 1"] {
+        let rule = new(expr).unwrap();
+        assert_eq!(
+            runtime.block_on(rule.test_async(&context)).ok(),
+            rule.test_with_context(&context).ok(),
+            "{expr}"
+        );
+    }
+
+    // Host functions are called once per condition, and never with the
+    // stand-ins for paths that haven't been fetched yet
+    let calls = Arc::new(Mutex::new(vec![]));
+    let mut functions = Functions::new();
+    let recorded = calls.clone();
+    functions.register("audit", &[Type::Any], Type::Bool, move |args| {
+        recorded.lock().unwrap().push(args[0].clone());
+        Ok(Value::Bool(true))
+    });
+    let options = Options {
+        functions,
+        ..Default::default()
+    };
+    let context = HashMap::from([
+        (vec!["a"], Value::Number(3.0)),
+        (vec!["b", "c"], Value::Number(4.0)),
+        (vec!["s"], Value::Str("x".to_string())),
+        (
+            vec!["items"],
+            Value::List(vec![Value::Number(1.0), Value::Number(2.0)]),
+        ),
+    ]);
+    let exprs = [
+        (
+            "audit(a) = true and audit(b.c) = true and audit(s) = true",
+            true,
+            vec![
+                Value::Number(3.0),
+                Value::Number(4.0),
+                Value::Str("x".to_string()),
+            ],
+        ),
+        // Called before a path in the same condition is fetched
+        ("audit(1) = a", false, vec![Value::Number(1.0)]),
+        (
+            "any(i in items: audit(i) = true and i = a)",
+            false,
+            vec![Value::Number(1.0), Value::Number(2.0)],
+        ),
+    ];
+    for (expr, expected, called) in exprs {
+        calls.lock().unwrap().clear();
+        let rule = new_with_options(expr, options.clone()).unwrap();
+        assert_eq!(
+            runtime.block_on(rule.test_async(&context)).unwrap(),
+            expected,
+            "{expr}"
+        );
+        assert_eq!(*calls.lock().unwrap(), called, "{expr}");
+    }
+
+    // So it can be spawned onto a multi-threaded runtime
+    fn assert_send<T: Send>(_: T) {}
+    assert_send(new("a = 1").unwrap().test_async(&context));
+}
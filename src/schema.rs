@@ -0,0 +1,741 @@
+use crate::{
+    functions::{Function, Type},
+    parser::{
+        Accessor, BinOp, BooleanCondition, BooleanExpression, PropertyVal, SimpleValue, Span,
+        Spanned, ValueExpression,
+    },
+    Value,
+};
+use std::{collections::HashMap, error::Error, fmt};
+
+/// The shape of the context rules will be evaluated against, for checking
+/// rules with [`CoolRule::check`](crate::CoolRule::check) before they're used.
+///
+/// # Examples
+///
+/// ```
+/// use coolrule::{Field, Schema, Type, Value};
+///
+/// let mut schema = Schema::new();
+/// schema.insert("user.age", Field::new(Type::Number));
+/// schema.insert("user.roles", Field::new(Type::List));
+/// schema.insert(
+///     "user.plan",
+///     Field {
+///         nullable: true,
+///         values: vec![Value::Str("free".into()), Value::Str("pro".into())],
+///         ..Field::new(Type::Str)
+///     },
+/// );
+///
+/// let expr = coolrule::new("user.age > 18 and \"admin\" in user.roles").unwrap();
+/// assert!(expr.check(&schema).is_ok());
+///
+/// let expr = coolrule::new("user.age > \"thirty\" or user.plan = \"gold\"").unwrap();
+/// let errors: Vec<_> = expr.check(&schema).unwrap_err().iter().map(|e| e.to_string()).collect();
+/// assert_eq!(
+///     errors,
+///     [
+///         "can't apply `>` to number and string",
+///         "\"gold\" isn't an allowed value of user.plan",
+///     ]
+/// );
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Schema {
+    fields: HashMap<Vec<String>, Field>,
+}
+
+/// A value declared in a [`Schema`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Field {
+    /// The type of the value. [`Type::Any`] allows every type.
+    pub ty: Type,
+    /// Whether the value can be `none` as well. Rules that compare a value
+    /// that can't be `none` with `none`, or order one that can, are reported.
+    pub nullable: bool,
+    /// The only values allowed, e.g. the names of plans. Empty allows every
+    /// value of the type.
+    pub values: Vec<Value>,
+}
+
+impl Field {
+    /// A value of type `ty` that can't be `none` and isn't restricted further.
+    pub fn new(ty: Type) -> Field {
+        Field {
+            ty,
+            nullable: false,
+            values: vec![],
+        }
+    }
+}
+
+impl Schema {
+    pub fn new() -> Schema {
+        Schema::default()
+    }
+
+    /// Declares the value at `path`, e.g. `user.age`. The items of a list are
+    /// declared with `[*]`, e.g. `items[*].price`. Maps and lists with nothing
+    /// declared below them can hold anything.
+    pub fn insert(&mut self, path: &str, field: Field) {
+        let mut key = vec![];
+        for segment in path.split('.') {
+            let name = segment.trim_end_matches("[*]");
+            key.push(name.to_string());
+            for _ in 0..(segment.len() - name.len()) / 3 {
+                key.push("*".to_string());
+            }
+        }
+        self.fields.insert(key, field);
+    }
+
+    // Whether `key` is below a map or list whose contents aren't declared
+    fn is_open(&self, key: &[String]) -> bool {
+        (1..key.len()).rev().any(|prefix| {
+            let prefix = &key[..prefix];
+            match self.fields.get(prefix) {
+                Some(field) => {
+                    matches!(field.ty, Type::Map | Type::List | Type::Any)
+                        && !self
+                            .fields
+                            .keys()
+                            .any(|k| k.len() > prefix.len() && k.starts_with(prefix))
+                }
+                None => false,
+            }
+        })
+    }
+}
+
+// Renders a schema key, e.g. `items[*].price`
+fn describe(key: &[String]) -> String {
+    let mut path = String::new();
+    for segment in key {
+        match segment.as_str() {
+            "*" => path.push_str("[*]"),
+            _ if path.is_empty() => path.push_str(segment),
+            _ => path.push_str(&format!(".{segment}")),
+        }
+    }
+    path
+}
+
+// What's known about a value before evaluation
+#[derive(Clone)]
+struct Known {
+    ty: Type,
+    // How the value is described in errors
+    name: String,
+    // Where the value is declared in the schema, for its allowed values and
+    // the fields or items below it
+    key: Option<Vec<String>>,
+    // The value itself, if it's written in the expression
+    literal: Option<SimpleValue>,
+}
+
+impl Known {
+    fn of(ty: Type) -> Known {
+        Known {
+            ty,
+            name: ty.to_string(),
+            key: None,
+            literal: None,
+        }
+    }
+}
+
+// A side of a comparison
+enum Side {
+    Value(Known),
+    Group(Vec<Known>),
+}
+
+impl Side {
+    fn type_name(&self) -> String {
+        match self {
+            Side::Value(known) => known.ty.to_string(),
+            Side::Group(_) => "group".to_string(),
+        }
+    }
+
+    fn is_any(&self) -> bool {
+        matches!(self, Side::Value(known) if known.ty == Type::Any)
+    }
+
+    fn is_iterable(&self) -> bool {
+        match self {
+            Side::Value(known) => matches!(known.ty, Type::List | Type::Any),
+            Side::Group(_) => true,
+        }
+    }
+
+    fn name(&self) -> String {
+        match self {
+            Side::Value(known) => known.name.clone(),
+            Side::Group(_) => "group".to_string(),
+        }
+    }
+}
+
+struct Checker<'a> {
+    schema: &'a Schema,
+    // Quantifier variables along with the schema key of the items they take,
+    // if it's known
+    bound: Vec<(&'a str, Option<Vec<String>>)>,
+    errors: Vec<CheckError>,
+}
+
+impl<'a> Checker<'a> {
+    fn error(&mut self, kind: CheckErrorKind, span: Span) {
+        self.errors.push(CheckError { kind, span });
+    }
+
+    fn declared(&mut self, key: Vec<String>, span: Span) -> Known {
+        match self.schema.fields.get(&key) {
+            Some(field) => Known {
+                ty: field.ty,
+                name: describe(&key),
+                key: Some(key),
+                literal: None,
+            },
+            // A map whose fields are declared
+            None if self.schema.fields.keys().any(|k| k.starts_with(&key)) => Known {
+                ty: Type::Map,
+                name: describe(&key),
+                key: Some(key),
+                literal: None,
+            },
+            None => {
+                if !self.schema.is_open(&key) {
+                    self.error(CheckErrorKind::UnknownVariable { path: key }, span);
+                }
+                Known::of(Type::Any)
+            }
+        }
+    }
+
+    fn path(&mut self, path: &[String], span: Span) -> Known {
+        let key = match self.bound.iter().rev().find(|(name, _)| *name == path[0]) {
+            Some((_, Some(items))) => [items.as_slice(), &path[1..]].concat(),
+            Some((_, None)) => return Known::of(Type::Any),
+            None => path.to_vec(),
+        };
+        self.declared(key, span)
+    }
+
+    // The items of a list
+    fn items(&mut self, list: &Known, span: Span) -> Known {
+        match &list.key {
+            Some(key) => self.declared([key.as_slice(), &["*".to_string()]].concat(), span),
+            None => Known::of(Type::Any),
+        }
+    }
+
+    // Ordering a value that can be none
+    fn ordered(&mut self, op: &str, side: &Side, span: Span) {
+        let Side::Value(known) = side else {
+            return;
+        };
+        let field = known
+            .key
+            .as_ref()
+            .and_then(|key| self.schema.fields.get(key));
+        if field.is_some_and(|field| field.nullable) {
+            let (op, value) = (op.to_string(), known.name.clone());
+            self.error(CheckErrorKind::Nullable { op, value }, span);
+        }
+    }
+
+    fn numeric(&mut self, op: String, known: &Known, span: Span) {
+        if !matches!(known.ty, Type::Number | Type::Any) {
+            let value = known.name.clone();
+            self.error(CheckErrorKind::NonNumeric { op, value }, span);
+        }
+    }
+
+    fn value_expression(
+        &mut self,
+        value_expression: &'a Spanned<ValueExpression>,
+        span: Span,
+    ) -> Known {
+        match &value_expression.node {
+            ValueExpression::Value(SimpleValue::PropertyPath(path)) => {
+                self.path(path, value_expression.span)
+            }
+            ValueExpression::Value(v) => Known {
+                ty: Type::of(v),
+                name: v.to_string(),
+                key: None,
+                literal: Some(v.clone()),
+            },
+            ValueExpression::Access(path, accessors) => {
+                let mut known = self.path(path, value_expression.span);
+                let mut projected = false;
+                for accessor in accessors {
+                    if known.ty == Type::Any {
+                        break;
+                    }
+                    known = match accessor {
+                        Accessor::Index(_) | Accessor::Wildcard if known.ty != Type::List => {
+                            let value = known.name.clone();
+                            self.error(CheckErrorKind::NotIterable { value }, span);
+                            return Known::of(Type::Any);
+                        }
+                        Accessor::Index(_) | Accessor::Wildcard => {
+                            projected |= matches!(accessor, Accessor::Wildcard);
+                            self.items(&known, value_expression.span)
+                        }
+                        Accessor::Field(name) => match known.key {
+                            Some(key) => self.declared(
+                                [key.as_slice(), std::slice::from_ref(name)].concat(),
+                                value_expression.span,
+                            ),
+                            None => Known::of(Type::Any),
+                        },
+                    };
+                }
+                match projected {
+                    true => Known::of(Type::List),
+                    false => known,
+                }
+            }
+            ValueExpression::Negate(operand) => {
+                let operand = self.value_expression(operand, span);
+                self.numeric("-".to_string(), &operand, span);
+                Known::of(Type::Number)
+            }
+            ValueExpression::Arithmetic(lhs, arith_op, rhs) => {
+                let lhs = self.value_expression(lhs, span);
+                let rhs = self.value_expression(rhs, span);
+                self.numeric(arith_op.to_string(), &lhs, span);
+                self.numeric(arith_op.to_string(), &rhs, span);
+                Known::of(Type::Number)
+            }
+            ValueExpression::Call(function, args) => {
+                for (i, arg) in args.iter().enumerate() {
+                    let arg = self.value_expression(arg, span);
+                    self.argument(function, i, arg.ty, span);
+                }
+                Known::of(function.returns())
+            }
+        }
+    }
+
+    // Built-ins check their own arguments, registered functions declare theirs
+    fn argument(&mut self, function: &Function, index: usize, got: Type, span: Span) {
+        match function.params().get(index) {
+            Some(&expected) if got != Type::Any && !expected.accepts(got) => self.error(
+                CheckErrorKind::InvalidArgument {
+                    function: function.name().to_string(),
+                    index: index + 1,
+                    expected,
+                    got,
+                },
+                span,
+            ),
+            _ => (),
+        }
+    }
+
+    fn side(&mut self, property_val: &'a PropertyVal, span: Span) -> Side {
+        match property_val {
+            PropertyVal::Value(v) => Side::Value(self.value_expression(v, span)),
+            PropertyVal::Group(members) => Side::Group(
+                members
+                    .iter()
+                    .map(|v| self.value_expression(v, span))
+                    .collect(),
+            ),
+        }
+    }
+
+    // Literals compared with a value that only allows some values
+    fn allowed(&mut self, value: &Known, literals: &[&Known], span: Span) {
+        let Some(field) = value
+            .key
+            .as_ref()
+            .and_then(|key| self.schema.fields.get(key))
+        else {
+            return;
+        };
+        for literal in literals {
+            let Some(v) = &literal.literal else {
+                continue;
+            };
+            let allowed = match v {
+                SimpleValue::None => field.nullable || matches!(field.ty, Type::Any | Type::None),
+                v => field.values.is_empty() || field.values.contains(&Value::from(v.clone())),
+            };
+            if !allowed {
+                let kind = CheckErrorKind::NotAllowed {
+                    path: value.key.clone().unwrap(),
+                    value: match v {
+                        SimpleValue::Str(s) => format!("{s:?}"),
+                        v => v.to_string(),
+                    },
+                };
+                self.error(kind, span);
+            }
+        }
+    }
+
+    fn comparison(
+        &mut self,
+        lval: &'a PropertyVal,
+        bin_op: BinOp,
+        rval: &'a PropertyVal,
+        span: Span,
+    ) {
+        let (left, right) = (self.side(lval, span), self.side(rval, span));
+        let mismatch = CheckErrorKind::TypeMismatch {
+            op: bin_op.to_string(),
+            left: left.type_name(),
+            right: right.type_name(),
+        };
+        match bin_op {
+            BinOp::GreaterThan
+            | BinOp::GreaterThanOrEqual
+            | BinOp::LessThan
+            | BinOp::LessThanOrEqual => {
+                if !left.is_any() && !right.is_any() && left.type_name() != right.type_name() {
+                    self.error(mismatch, span);
+                }
+                let op = bin_op.to_string();
+                self.ordered(&op, &left, span);
+                self.ordered(&op, &right, span);
+            }
+            BinOp::Equal | BinOp::NotEqual | BinOp::Is | BinOp::IsNot => {
+                let none = |side: &Side| matches!(side, Side::Value(k) if k.ty == Type::None);
+                if !left.is_any()
+                    && !right.is_any()
+                    && !none(&left)
+                    && !none(&right)
+                    && left.type_name() != right.type_name()
+                {
+                    self.error(mismatch, span);
+                } else if let (Side::Value(l), Side::Value(r)) = (&left, &right) {
+                    self.allowed(l, &[r], span);
+                    self.allowed(r, &[l], span);
+                }
+            }
+            BinOp::In | BinOp::NotIn => {
+                if !right.is_iterable() {
+                    let value = right.name();
+                    self.error(CheckErrorKind::NotIterable { value }, span);
+                } else if let (Side::Value(l), Side::Group(members)) = (&left, &right) {
+                    self.allowed(l, &members.iter().collect::<Vec<_>>(), span);
+                }
+            }
+            BinOp::SubSetOf
+            | BinOp::SuperSetOf
+            | BinOp::IntersectionOf
+            | BinOp::NotIntersectionOf => {
+                for side in [&left, &right] {
+                    if !side.is_iterable() {
+                        let value = side.name();
+                        self.error(CheckErrorKind::NotIterable { value }, span);
+                    }
+                }
+            }
+            // A list can contain any value, a string only other strings
+            BinOp::Contains if left.is_iterable() && matches!(right, Side::Value(_)) => {}
+            BinOp::StartsWith
+            | BinOp::EndsWith
+            | BinOp::Contains
+            | BinOp::Like
+            | BinOp::ILike
+            | BinOp::Glob
+            | BinOp::IGlob => {
+                let string = |side: &Side| matches!(side, Side::Value(k) if matches!(k.ty, Type::Str | Type::Any));
+                if !string(&left) || !string(&right) {
+                    self.error(mismatch, span);
+                }
+            }
+        }
+    }
+
+    fn boolean_condition(&mut self, boolean_condition: &'a Spanned<BooleanCondition>) {
+        let span = boolean_condition.span;
+        match &boolean_condition.node {
+            BooleanCondition::Comparison(lval, bin_op, rval) => {
+                self.comparison(&lval.node, bin_op.node, &rval.node, span)
+            }
+            BooleanCondition::Match(lval, match_op, _) => {
+                let left = self.side(&lval.node, span);
+                if !left.is_any() && !matches!(&left, Side::Value(k) if k.ty == Type::Str) {
+                    let kind = CheckErrorKind::TypeMismatch {
+                        op: match_op.node.to_string(),
+                        left: left.type_name(),
+                        right: "pattern".to_string(),
+                    };
+                    self.error(kind, span);
+                }
+            }
+            BooleanCondition::Quantified(_, variable, collection, body) => {
+                let items = match self.side(&collection.node, span) {
+                    Side::Value(known) if known.ty == Type::List => {
+                        self.items(&known, collection.span).key
+                    }
+                    side if !side.is_iterable() => {
+                        let value = side.name();
+                        self.error(CheckErrorKind::NotIterable { value }, span);
+                        None
+                    }
+                    _ => None,
+                };
+                self.bound.push((&variable.node, items));
+                self.boolean_expression(body);
+                self.bound.pop();
+            }
+            BooleanCondition::Group(expr) => self.boolean_expression(expr),
+            BooleanCondition::Not(cond) => self.boolean_condition(cond),
+        }
+    }
+
+    fn boolean_expression(&mut self, boolean_expression: &'a BooleanExpression) {
+        match boolean_expression {
+            BooleanExpression::Condition(cond) => self.boolean_condition(cond),
+            BooleanExpression::Binary(lhs, _, rhs) => {
+                self.boolean_expression(lhs);
+                self.boolean_expression(rhs);
+            }
+        }
+    }
+}
+
+pub fn check(
+    boolean_expression: &BooleanExpression,
+    schema: &Schema,
+) -> Result<(), Vec<CheckError>> {
+    let mut checker = Checker {
+        schema,
+        bound: vec![],
+        errors: vec![],
+    };
+    checker.boolean_expression(boolean_expression);
+    match checker.errors.is_empty() {
+        true => Ok(()),
+        false => Err(checker.errors),
+    }
+}
+
+/// A problem found by [`CoolRule::check`](crate::CoolRule::check) that would
+/// make the rule fail or always compare false at runtime.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CheckError {
+    kind: CheckErrorKind,
+    span: Span,
+}
+impl CheckError {
+    pub fn kind(&self) -> &CheckErrorKind {
+        &self.kind
+    }
+
+    /// The span of the property path that isn't in the schema, or of the
+    /// comparison that's wrong, in the source expression.
+    pub fn span(&self) -> Span {
+        self.span
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CheckErrorKind {
+    /// The property path isn't declared in the schema, e.g. `["user", "name"]`
+    /// for `user.name`. The items of lists are `*`.
+    UnknownVariable { path: Vec<String> },
+    /// An operator was applied to types it can't compare, e.g. `>` to a
+    /// number and a string.
+    TypeMismatch {
+        op: String,
+        left: String,
+        right: String,
+    },
+    /// A set operator or quantifier was applied to something that isn't a
+    /// list or group.
+    NotIterable { value: String },
+    /// An arithmetic operator was applied to something that isn't a number.
+    NonNumeric { op: String, value: String },
+    /// An ordering operator was applied to a value the schema says can be
+    /// `none`.
+    Nullable { op: String, value: String },
+    /// A value is compared with a literal the schema doesn't allow for it.
+    NotAllowed { path: Vec<String>, value: String },
+    /// A registered function is passed an argument of the wrong type.
+    InvalidArgument {
+        function: String,
+        index: usize,
+        expected: Type,
+        got: Type,
+    },
+}
+
+impl Error for CheckError {}
+impl fmt::Display for CheckError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{0}", self.kind)
+    }
+}
+impl fmt::Display for CheckErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CheckErrorKind::UnknownVariable { path } => {
+                write!(f, "{} isn't in the schema", describe(path))
+            }
+            CheckErrorKind::TypeMismatch { op, left, right } => {
+                write!(f, "can't apply `{op}` to {left} and {right}")
+            }
+            CheckErrorKind::NotIterable { value } => write!(f, "{value} is not iterable"),
+            CheckErrorKind::NonNumeric { op, value } => {
+                write!(f, "can't apply `{op}` to {value}, it isn't a number")
+            }
+            CheckErrorKind::Nullable { op, value } => {
+                write!(f, "can't apply `{op}` to {value}, it can be none")
+            }
+            CheckErrorKind::NotAllowed { path, value } => {
+                write!(f, "{value} isn't an allowed value of {}", describe(path))
+            }
+            CheckErrorKind::InvalidArgument {
+                function,
+                index,
+                expected,
+                got,
+            } => write!(
+                f,
+                "`{function}` expects a {expected} for argument {index} but got a {got}"
+            ),
+        }
+    }
+}
+
+#[test]
+fn test_check() {
+    use crate::{parser::parse, Functions, Options};
+
+    let mut schema = Schema::new();
+    schema.insert("age", Field::new(Type::Number));
+    schema.insert("name", Field::new(Type::Str));
+    schema.insert("tags", Field::new(Type::List));
+    schema.insert("meta", Field::new(Type::Map));
+    schema.insert("items", Field::new(Type::List));
+    schema.insert("items[*].price", Field::new(Type::Number));
+    schema.insert("items[*].sku", Field::new(Type::Str));
+    schema.insert("user.verified", Field::new(Type::Bool));
+    schema.insert(
+        "user.plan",
+        Field {
+            nullable: true,
+            values: vec![Value::Str("free".into()), Value::Str("pro".into())],
+            ..Field::new(Type::Str)
+        },
+    );
+
+    let mut functions = Functions::new();
+    functions.register("double", &[Type::Number], Type::Number, |_| Ok(Value::None));
+    let options = Options {
+        functions,
+        ..Default::default()
+    };
+
+    let exprs = [
+        ("age > 18 and name = \"ada\"", vec![]),
+        ("\"x\" in tags and tags ∩ (\"a\", \"b\")", vec![]),
+        ("meta.anything.at_all = 1", vec![]),
+        (
+            "items[0].price * 2 > age and \"a1\" in items[*].sku",
+            vec![],
+        ),
+        (
+            "any(item in items: item.price > 100 and item.sku starts_with \"a\")",
+            vec![],
+        ),
+        (
+            "any(x in (1, 2): x.y = 1) and any(t in tags: t.z = 1)",
+            vec![],
+        ),
+        (
+            "user.plan in (\"free\", \"pro\") or user.plan is none",
+            vec![],
+        ),
+        ("tags contains \"a\" and name contains \"a\"", vec![]),
+        (
+            "len(name) > 3 and lower(name) = \"ada\" and double(age) < 1",
+            vec![],
+        ),
+        (
+            "age > \"thirty\"",
+            vec!["can't apply `>` to number and string"],
+        ),
+        (
+            "user.verified = 1",
+            vec!["can't apply `=` to bool and number"],
+        ),
+        ("name > age", vec!["can't apply `>` to string and number"]),
+        ("user.name = 1", vec!["user.name isn't in the schema"]),
+        ("items[0].qty = 1", vec!["items[*].qty isn't in the schema"]),
+        (
+            "all(item in items: item.qty > 1)",
+            vec!["items[*].qty isn't in the schema"],
+        ),
+        ("1 in age", vec!["age is not iterable"]),
+        ("any(x in name: x = 1)", vec!["name is not iterable"]),
+        ("age[0] = 1", vec!["age is not iterable"]),
+        ("tags ⊆ age", vec!["age is not iterable"]),
+        (
+            "name + 1 = 2",
+            vec!["can't apply `+` to name, it isn't a number"],
+        ),
+        (
+            "age starts_with \"1\"",
+            vec!["can't apply `starts_with` to number and string"],
+        ),
+        (
+            "age contains 1",
+            vec!["can't apply `contains` to number and number"],
+        ),
+        (
+            "age =~ \"1\"",
+            vec!["can't apply `=~` to number and pattern"],
+        ),
+        (
+            "user.plan = \"gold\"",
+            vec!["\"gold\" isn't an allowed value of user.plan"],
+        ),
+        (
+            "user.plan in (\"pro\", \"platinum\")",
+            vec!["\"platinum\" isn't an allowed value of user.plan"],
+        ),
+        ("age is none", vec!["none isn't an allowed value of age"]),
+        (
+            "name in (\"ada\", none)",
+            vec!["none isn't an allowed value of name"],
+        ),
+        (
+            "user.plan >= \"free\"",
+            vec!["can't apply `>=` to user.plan, it can be none"],
+        ),
+        (
+            "double(name) = 1",
+            vec!["`double` expects a number for argument 1 but got a string"],
+        ),
+        (
+            "age > name or missing = 1",
+            vec![
+                "can't apply `>` to number and string",
+                "missing isn't in the schema",
+            ],
+        ),
+    ];
+    for (expr, expected) in exprs.iter() {
+        let boolean_expression = parse(expr, &options).unwrap();
+        let errors: Vec<String> = match check(&boolean_expression, &schema) {
+            Ok(()) => vec![],
+            Err(errors) => errors.iter().map(|e| e.to_string()).collect(),
+        };
+        assert_eq!(errors, *expected, "{expr}");
+    }
+
+    let boolean_expression = parse("age = 1 and user.nme = 2", &options).unwrap();
+    let errors = check(&boolean_expression, &schema).unwrap_err();
+    assert_eq!(errors[0].span(), Span { start: 12, end: 20 });
+}
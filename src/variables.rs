@@ -0,0 +1,147 @@
+use crate::parser::{
+    BooleanCondition, BooleanExpression, PropertyVal, SimpleValue, Span, Spanned, ValueExpression,
+};
+
+/// A property path that a rule reads from its context.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Variable {
+    /// The segments of the path, e.g. `["user", "age"]` for `user.age`, or
+    /// `["items"]` for `items[0].price`.
+    pub path: Vec<String>,
+    /// Where the path appears in the expression, in source order.
+    pub spans: Vec<Span>,
+}
+
+// Paths under a name bound by an enclosing quantifier refer to the items
+// being iterated over rather than the context, so they're left out
+struct Collector<'a> {
+    bound: Vec<&'a str>,
+    variables: Vec<Variable>,
+}
+
+impl<'a> Collector<'a> {
+    fn add(&mut self, path: &[String], span: Span) {
+        if self.bound.contains(&path[0].as_str()) {
+            return;
+        }
+        match self.variables.iter_mut().find(|v| v.path == path) {
+            Some(variable) => variable.spans.push(span),
+            None => self.variables.push(Variable {
+                path: path.to_vec(),
+                spans: vec![span],
+            }),
+        }
+    }
+
+    fn value_expression(&mut self, value_expression: &'a Spanned<ValueExpression>) {
+        match &value_expression.node {
+            ValueExpression::Value(SimpleValue::PropertyPath(path))
+            | ValueExpression::Access(path, _) => self.add(path, value_expression.span),
+            ValueExpression::Value(_) => (),
+            ValueExpression::Negate(operand) => self.value_expression(operand),
+            ValueExpression::Call(_, args) => {
+                args.iter().for_each(|arg| self.value_expression(arg))
+            }
+            ValueExpression::Arithmetic(lhs, _, rhs) => {
+                self.value_expression(lhs);
+                self.value_expression(rhs);
+            }
+        }
+    }
+
+    fn property_val(&mut self, property_val: &'a PropertyVal) {
+        match property_val {
+            PropertyVal::Value(v) => self.value_expression(v),
+            PropertyVal::Group(members) => members.iter().for_each(|v| self.value_expression(v)),
+        }
+    }
+
+    fn boolean_condition(&mut self, boolean_condition: &'a BooleanCondition) {
+        match boolean_condition {
+            BooleanCondition::Comparison(lval, _, rval) => {
+                self.property_val(&lval.node);
+                self.property_val(&rval.node);
+            }
+            BooleanCondition::Match(lval, _, _) => self.property_val(&lval.node),
+            BooleanCondition::Quantified(_, variable, collection, body) => {
+                self.property_val(&collection.node);
+                self.bound.push(&variable.node);
+                self.boolean_expression(body);
+                self.bound.pop();
+            }
+            BooleanCondition::Group(expr) => self.boolean_expression(expr),
+            BooleanCondition::Not(cond) => self.boolean_condition(&cond.node),
+        }
+    }
+
+    fn boolean_expression(&mut self, boolean_expression: &'a BooleanExpression) {
+        match boolean_expression {
+            BooleanExpression::Condition(cond) => self.boolean_condition(&cond.node),
+            BooleanExpression::Binary(lhs, _, rhs) => {
+                self.boolean_expression(lhs);
+                self.boolean_expression(rhs);
+            }
+        }
+    }
+}
+
+pub fn variables(boolean_expression: &BooleanExpression) -> Vec<Variable> {
+    let mut collector = Collector {
+        bound: vec![],
+        variables: vec![],
+    };
+    collector.boolean_expression(boolean_expression);
+    collector.variables
+}
+
+#[test]
+fn test_variables() {
+    use crate::{parser::parse, Options};
+
+    let exprs = [
+        ("1 = 1", vec![]),
+        (
+            "a = 1 and b.c > a",
+            vec![("a", vec![(0, 1), (16, 17)]), ("b.c", vec![(10, 13)])],
+        ),
+        (
+            "x in (y, 1, z.w)",
+            vec![
+                ("x", vec![(0, 1)]),
+                ("y", vec![(6, 7)]),
+                ("z.w", vec![(12, 15)]),
+            ],
+        ),
+        (
+            "not len(s) + -n = items[0].price",
+            vec![
+                ("s", vec![(8, 9)]),
+                ("n", vec![(14, 15)]),
+                ("items", vec![(18, 32)]),
+            ],
+        ),
+        ("name =~ \"^a\"", vec![("name", vec![(0, 4)])]),
+        (
+            "any(x in xs: x.y > limit and all(y in x.ys: y = x.z))",
+            vec![("xs", vec![(9, 11)]), ("limit", vec![(19, 24)])],
+        ),
+        (
+            "any(x in xs: x = 1) and x = 2",
+            vec![("xs", vec![(9, 11)]), ("x", vec![(24, 25)])],
+        ),
+    ];
+    for (expr, expected) in exprs.iter() {
+        let boolean_expression = parse(expr, &Options::default()).unwrap();
+        let expected: Vec<Variable> = expected
+            .iter()
+            .map(|(path, spans)| Variable {
+                path: path.split('.').map(|s| s.to_string()).collect(),
+                spans: spans
+                    .iter()
+                    .map(|&(start, end)| Span { start, end })
+                    .collect(),
+            })
+            .collect();
+        assert_eq!(variables(&boolean_expression), expected, "{expr}");
+    }
+}
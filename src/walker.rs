@@ -0,0 +1,251 @@
+use crate::{
+    compiler::compile,
+    evaluator::{
+        arithmetic, compare, eval_access, eval_with_context as eval_compiled, is_match, negate,
+        EvalError, EvalErrorKind, Evaluation, Operand,
+    },
+    parser::{
+        AndOr, BooleanCondition, BooleanExpression, PropertyVal, Quantifier, SimpleValue, Spanned,
+        ValueExpression,
+    },
+    ContextProvider, Options, Value,
+};
+use std::{borrow::Cow, collections::HashMap};
+
+// Evaluates by walking the parsed expression, the way rules were evaluated
+// before they were compiled. It shares how values are compared with the
+// compiled rules but nothing else, so it's kept to test and time them against.
+
+// What property paths are looked up in: the context along with the variables
+// bound by any enclosing quantifiers
+struct Scope<'a> {
+    context: &'a dyn ContextProvider,
+    parent: Option<&'a Scope<'a>>,
+    variable: Option<(&'a str, SimpleValue)>,
+}
+
+impl<'a> Scope<'a> {
+    fn bind(&'a self, name: &'a str, value: SimpleValue) -> Scope<'a> {
+        Scope {
+            context: self.context,
+            parent: Some(self),
+            variable: Some((name, value)),
+        }
+    }
+}
+
+fn get(path: &[String], scope: &Scope) -> Result<SimpleValue, EvalErrorKind> {
+    let missing = || EvalErrorKind::MissingVariable {
+        path: path.to_vec(),
+    };
+    let mut variables = Some(scope);
+    while let Some(scope) = variables {
+        if let Some((name, bound)) = &scope.variable {
+            if *name == path[0] {
+                let mut value = bound;
+                for segment in &path[1..] {
+                    match value {
+                        SimpleValue::Map(map) => value = map.get(segment).ok_or_else(missing)?,
+                        _ => return Err(missing()),
+                    }
+                }
+                return Ok(value.clone());
+            }
+        }
+        variables = scope.parent;
+    }
+    let path: Vec<&str> = path.iter().map(|s| s.as_str()).collect();
+    match scope.context.get(&path) {
+        Some(value) => Ok(SimpleValue::from(&value)),
+        None => Err(missing()),
+    }
+}
+
+fn value(value_expression: &ValueExpression, scope: &Scope) -> Result<SimpleValue, EvalErrorKind> {
+    match value_expression {
+        ValueExpression::Value(SimpleValue::PropertyPath(path)) => get(path, scope),
+        ValueExpression::Value(v) => Ok(v.clone()),
+        ValueExpression::Access(path, accessors) => eval_access(path, get(path, scope)?, accessors),
+        ValueExpression::Call(function, args) => {
+            let args = args
+                .iter()
+                .map(|arg| value(&arg.node, scope))
+                .collect::<Result<Vec<_>, _>>()?;
+            function.call(&args)
+        }
+        ValueExpression::Negate(operand) => negate(&value(&operand.node, scope)?),
+        ValueExpression::Arithmetic(lhs, arith_op, rhs) => arithmetic(
+            &value(&lhs.node, scope)?,
+            *arith_op,
+            &value(&rhs.node, scope)?,
+        ),
+    }
+}
+
+fn operand(property_val: &PropertyVal, scope: &Scope) -> Result<Operand<'static>, EvalErrorKind> {
+    match property_val {
+        PropertyVal::Value(v) => match value(&v.node, scope)? {
+            SimpleValue::List(items) => Ok(Operand::Group(Cow::Owned(items))),
+            v => Ok(Operand::Value(Cow::Owned(v))),
+        },
+        PropertyVal::Group(gv) => gv
+            .iter()
+            .map(|v| value(&v.node, scope))
+            .collect::<Result<Vec<_>, _>>()
+            .map(|items| Operand::Group(Cow::Owned(items))),
+    }
+}
+
+fn condition(
+    boolean_condition: &Spanned<BooleanCondition>,
+    scope: &Scope,
+    options: &Options,
+) -> Result<bool, EvalError> {
+    let span = boolean_condition.span;
+    let failed = |kind| EvalError { kind, span };
+    match &boolean_condition.node {
+        BooleanCondition::Comparison(lval, bin_op, rval) => {
+            let lhs = operand(&lval.node, scope).map_err(failed)?;
+            let rhs = operand(&rval.node, scope).map_err(failed)?;
+            compare(lhs, bin_op.node, rhs, options.comparison).map_err(failed)
+        }
+        BooleanCondition::Match(lval, match_op, pattern) => {
+            let value = match &lval.node {
+                PropertyVal::Value(v) => value(&v.node, scope),
+                PropertyVal::Group(_) => Err(EvalErrorKind::TypeMismatch {
+                    op: match_op.node.to_string(),
+                    left: "group".to_string(),
+                    right: "pattern".to_string(),
+                }),
+            };
+            value
+                .and_then(|v| is_match(&v, match_op.node, &pattern.node))
+                .map_err(failed)
+        }
+        BooleanCondition::Quantified(quantifier, variable, collection, body) => {
+            let items = match operand(&collection.node, scope).map_err(failed)? {
+                Operand::Group(items) => items.into_owned(),
+                Operand::Value(value) => {
+                    return Err(failed(EvalErrorKind::NotIterable {
+                        value: value.to_string(),
+                    }))
+                }
+            };
+            let (decisive, result) = match quantifier {
+                Quantifier::Any => (true, true),
+                Quantifier::All => (false, false),
+                Quantifier::None => (true, false),
+            };
+            let mut outcome = !result;
+            for item in items {
+                if expression(body, &scope.bind(&variable.node, item), options)? == decisive {
+                    if options.evaluation == Evaluation::ShortCircuit {
+                        return Ok(result);
+                    }
+                    outcome = result;
+                }
+            }
+            Ok(outcome)
+        }
+        BooleanCondition::Group(expr) => expression(expr, scope, options),
+        BooleanCondition::Not(cond) => Ok(!condition(cond, scope, options)?),
+    }
+}
+
+fn expression(
+    boolean_expression: &BooleanExpression,
+    scope: &Scope,
+    options: &Options,
+) -> Result<bool, EvalError> {
+    match boolean_expression {
+        BooleanExpression::Condition(cond) => condition(cond, scope, options),
+        BooleanExpression::Binary(lhs, and_or, rhs) => {
+            let lhs = expression(lhs, scope, options)?;
+            if options.evaluation == Evaluation::ShortCircuit {
+                match (and_or, lhs) {
+                    (AndOr::And, false) => return Ok(false),
+                    (AndOr::Or, true) => return Ok(true),
+                    _ => (),
+                }
+            }
+            let rhs = expression(rhs, scope, options)?;
+            match and_or {
+                AndOr::And => Ok(lhs && rhs),
+                AndOr::Or => Ok(lhs || rhs),
+            }
+        }
+    }
+}
+
+pub fn eval_with_context(
+    boolean_expression: &BooleanExpression,
+    context: &dyn ContextProvider,
+    options: &Options,
+) -> Result<bool, EvalError> {
+    let scope = Scope {
+        context,
+        parent: None,
+        variable: None,
+    };
+    expression(boolean_expression, &scope, options)
+}
+
+// Evaluates the expression both compiled and by walking it, which must agree,
+// and returns the walked result
+pub fn eval_both(
+    boolean_expression: &BooleanExpression,
+    context: &dyn ContextProvider,
+    options: &Options,
+) -> Result<bool, EvalError> {
+    let walked = eval_with_context(boolean_expression, context, options);
+    let compiled = eval_compiled(&compile(boolean_expression), context, options);
+    assert_eq!(compiled, walked, "{boolean_expression}");
+    walked
+}
+
+// Times compiled rules against walking the parsed expression, which shows
+// what compiling saves. Run with
+// `cargo test --release -- --ignored --nocapture bench_compile`.
+#[test]
+#[ignore]
+fn bench_compile() {
+    use crate::parser::parse;
+    use std::time::Instant;
+
+    let context = HashMap::from([
+        (vec!["user", "age"], Value::Number(36.0)),
+        (vec!["user", "plan"], Value::Str("pro".to_string())),
+        (vec!["order", "total"], Value::Number(120.0)),
+        (
+            vec!["order", "tags"],
+            Value::List(vec![
+                Value::Str("gift".to_string()),
+                Value::Str("sale".to_string()),
+            ]),
+        ),
+    ]);
+    let exprs = [
+        "true == false or (1, 2, 3) ⊆ (1, 2, 3)",
+        "user.age >= 18 and user.plan != \"free\" and order.total > 100",
+        "user.plan in (\"GB\", \"FR\", \"DE\", \"ES\", \"IT\", \"NL\", \"pro\")",
+        "order.tags ⊆ (\"gift\", \"sale\", \"new\", \"bulk\") and order.tags ∩ (\"sale\")",
+        "any(tag in order.tags: tag = \"sale\" and user.age > 18)",
+    ];
+    let options = Options::default();
+    let iterations = 100_000;
+    for expr in exprs {
+        let boolean_expression = parse(expr, &options).unwrap();
+        let program = compile(&boolean_expression);
+        let start = Instant::now();
+        for _ in 0..iterations {
+            eval_compiled(&program, &context, &options).unwrap();
+        }
+        let compiled = start.elapsed() / iterations;
+        let start = Instant::now();
+        for _ in 0..iterations {
+            eval_with_context(&boolean_expression, &context, &options).unwrap();
+        }
+        let walked = start.elapsed() / iterations;
+        println!("{expr}\n  compiled: {compiled:?}, walked: {walked:?}");
+    }
+}